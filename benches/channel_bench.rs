@@ -0,0 +1,67 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rpos::channel::{Channel, Receiver, Sender};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+#[derive(Debug, Default, Clone, Copy)]
+struct ImuData {
+    gyro: [f32; 3],
+    acc: [f32; 3],
+    timestamp: u64,
+}
+
+/*
+    test the cost of send/try_read of the locked channel and the lock free channel
+*/
+fn bench_rxtx(c: &mut Criterion, name: &str, (tx, mut rx): (Sender<ImuData>, Receiver<ImuData>)) {
+    c.bench_function(&format!("{}_send", name), |b| {
+        b.iter(|| tx.send(black_box(ImuData::default())))
+    });
+
+    c.bench_function(&format!("{}_send_try_read", name), |b| {
+        b.iter(|| {
+            tx.send(black_box(ImuData::default()));
+            black_box(rx.try_read());
+        })
+    });
+}
+
+/*
+    test the cost of send while another thread keeps reading the channel
+*/
+fn bench_contended_send(
+    c: &mut Criterion,
+    name: &str,
+    (tx, rx): (Sender<ImuData>, Receiver<ImuData>),
+) {
+    let stop = Arc::new(AtomicBool::new(false));
+    let reader = {
+        let stop = stop.clone();
+        let mut rx = rx.clone();
+        std::thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                black_box(rx.try_read());
+            }
+        })
+    };
+
+    c.bench_function(&format!("{}_contended_send", name), |b| {
+        b.iter(|| tx.send(black_box(ImuData::default())))
+    });
+
+    stop.store(true, Ordering::Relaxed);
+    reader.join().unwrap();
+}
+
+fn test_channel_bench(c: &mut Criterion) {
+    bench_rxtx(c, "locked", Channel::<ImuData>::new());
+    bench_rxtx(c, "lock_free", Channel::<ImuData>::new_lock_free());
+    bench_contended_send(c, "locked", Channel::<ImuData>::new());
+    bench_contended_send(c, "lock_free", Channel::<ImuData>::new_lock_free());
+}
+
+criterion_group!(benches, test_channel_bench);
+
+criterion_main!(benches);
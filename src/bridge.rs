@@ -0,0 +1,552 @@
+use std::{
+    collections::HashMap,
+    io,
+    mem::size_of,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use crate::{
+    channel::{deferred, Sender},
+    msg::{
+        add_message, attach_message_schema, get_new_rx_of_message, get_new_tx_of_message,
+        MessageError,
+    },
+    pthread_scheduler::SchedulePthread,
+    shm::{fingerprint, Pod},
+};
+
+/*
+    a frame is one udp datagram, integers are little endian.
+    | magic u32 | version u8 | name_len u8 | payload_len u16 | seq u32 | fingerprint u64 | name | payload |
+    the payload is the message copied byte by byte, so only Pod messages could be bridged,
+    the schema fingerprint of the message type keeps a changed layout from being reinterpreted.
+*/
+const FRAME_MAGIC: u32 = 0x52504252; // "RPBR"
+pub const FRAME_VERSION: u8 = 2;
+const HEADER_LEN: usize = 20;
+const MAX_DATAGRAM: usize = 65507;
+
+// how often the receiving loop checks whether it's stopped
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+struct Frame<'a> {
+    name: &'a str,
+    seq: u32,
+    fingerprint: u64,
+    payload: &'a [u8],
+}
+
+fn encode_frame(name: &str, seq: u32, fingerprint: u64, payload: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(HEADER_LEN + name.len() + payload.len());
+    buf.extend_from_slice(&FRAME_MAGIC.to_le_bytes());
+    buf.push(FRAME_VERSION);
+    buf.push(name.len() as u8);
+    buf.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+    buf.extend_from_slice(&seq.to_le_bytes());
+    buf.extend_from_slice(&fingerprint.to_le_bytes());
+    buf.extend_from_slice(name.as_bytes());
+    buf.extend_from_slice(payload);
+    buf
+}
+
+// None if it's not a frame of this version
+fn decode_frame(buf: &[u8]) -> Option<Frame<'_>> {
+    let header = buf.get(..HEADER_LEN)?;
+    if u32::from_le_bytes(header[0..4].try_into().unwrap()) != FRAME_MAGIC
+        || header[4] != FRAME_VERSION
+    {
+        return None;
+    }
+    let name_len = header[5] as usize;
+    let payload_len = u16::from_le_bytes(header[6..8].try_into().unwrap()) as usize;
+    let seq = u32::from_le_bytes(header[8..12].try_into().unwrap());
+    let fingerprint = u64::from_le_bytes(header[12..20].try_into().unwrap());
+
+    let body = &buf[HEADER_LEN..];
+    if body.len() != name_len + payload_len {
+        return None;
+    }
+    Some(Frame {
+        name: std::str::from_utf8(&body[..name_len]).ok()?,
+        seq,
+        fingerprint,
+        payload: &body[name_len..],
+    })
+}
+
+// Pod has no padding bytes, so every byte of it is initialized
+fn as_bytes<T: Pod>(msg: &T) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(msg as *const T as *const u8, size_of::<T>()) }
+}
+
+fn invalid_input<E>(e: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidInput, e)
+}
+
+fn invalid_data(e: MessageError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+// publishes the payload of received frames to a local topic
+trait Republish: Send {
+    fn accepts(&self, payload: &[u8]) -> bool;
+    fn publish(&self, payload: &[u8]);
+}
+
+impl<T: Pod> Republish for Sender<T> {
+    fn accepts(&self, payload: &[u8]) -> bool {
+        payload.len() == size_of::<T>()
+    }
+
+    // the length is checked by accepts, and any bytes are a valid Pod
+    fn publish(&self, payload: &[u8]) {
+        self.send(unsafe { std::ptr::read_unaligned(payload.as_ptr() as *const T) });
+    }
+}
+
+struct RemoteTopic {
+    republisher: Box<dyn Republish>,
+    fingerprint: u64,
+    last_seq: Option<u32>,
+    mismatched: bool, // a frame of another schema is received, only report it once
+}
+
+impl RemoteTopic {
+    // frames skipped since the last one, a frame going backwards(like a restarted peer) is not a loss
+    fn lost_before(&mut self, seq: u32) -> u64 {
+        let lost = match self.last_seq {
+            Some(last) => seq.wrapping_sub(last).wrapping_sub(1),
+            None => 0,
+        };
+        self.last_seq = Some(seq);
+        if lost < u32::MAX / 2 {
+            lost as u64
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BridgeStats {
+    pub sent_cnt: u64,
+    pub received_cnt: u64,
+    pub dropped_cnt: u64, // received frames which are malformed, of another version or unknown topics
+    pub lost_cnt: u64,    // frames never received, found by gaps of the sequence number
+    pub mismatch_cnt: u64, // frames of a known topic with another schema fingerprint, not published
+}
+
+// unregisters the callback of `send_topic`
+type StopForward = Box<dyn FnOnce() + Send>;
+
+/*
+    mirrors msg topics between processes over udp.
+    `send_topic` forwards every message of a topic to a peer until `stop_topic`, `recv_topic`
+    republishes the frames received from peers, `start` runs the receiving loop on a SchedulePthread.
+    forwarding runs in the work queue thread, so it never delays the publisher.
+    errors found in the background are kept for `take_error` instead of printed.
+*/
+pub struct Bridge {
+    socket: UdpSocket,
+    remote_topics: Mutex<HashMap<String, RemoteTopic>>, // by the topic name of the peer
+    forwards: Mutex<HashMap<(String, SocketAddr), StopForward>>, // by the topic name and peer
+    error: Mutex<Option<io::Error>>,
+    running: AtomicBool,
+    sent_cnt: AtomicU64,
+    received_cnt: AtomicU64,
+    dropped_cnt: AtomicU64,
+    lost_cnt: AtomicU64,
+    mismatch_cnt: AtomicU64,
+}
+
+impl Bridge {
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<Arc<Self>> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+        Ok(Arc::new(Bridge {
+            socket,
+            remote_topics: Mutex::new(HashMap::new()),
+            forwards: Mutex::new(HashMap::new()),
+            error: Mutex::new(None),
+            running: AtomicBool::new(true),
+            sent_cnt: AtomicU64::new(0),
+            received_cnt: AtomicU64::new(0),
+            dropped_cnt: AtomicU64::new(0),
+            lost_cnt: AtomicU64::new(0),
+            mismatch_cnt: AtomicU64::new(0),
+        }))
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    pub fn stats(&self) -> BridgeStats {
+        BridgeStats {
+            sent_cnt: self.sent_cnt.load(Ordering::Acquire),
+            received_cnt: self.received_cnt.load(Ordering::Acquire),
+            dropped_cnt: self.dropped_cnt.load(Ordering::Acquire),
+            lost_cnt: self.lost_cnt.load(Ordering::Acquire),
+            mismatch_cnt: self.mismatch_cnt.load(Ordering::Acquire),
+        }
+    }
+
+    /// the last error of the receiving loop or a received frame, like a topic of another schema,
+    /// and clear it. it's None if nothing went wrong since the last call.
+    pub fn take_error(&self) -> Option<io::Error> {
+        self.error.lock().unwrap().take()
+    }
+
+    fn set_error(&self, e: io::Error) {
+        *self.error.lock().unwrap() = Some(e);
+    }
+
+    /// send every message of topic `name` to `peer`, the topic is subscribed if it's not added yet.
+    /// returns `AlreadyExists` if the topic is sent to the peer already.
+    /// it's sent until `stop_topic`, or until the bridge is dropped.
+    pub fn send_topic<T: Pod>(
+        self: &Arc<Self>,
+        name: &str,
+        peer: impl ToSocketAddrs,
+    ) -> io::Result<()> {
+        if name.len() > u8::MAX as usize {
+            return Err(invalid_input(format!("topic name {} is too long", name)));
+        }
+        if HEADER_LEN + name.len() + size_of::<T>() > MAX_DATAGRAM {
+            return Err(invalid_input(format!(
+                "message of {} is too large for udp",
+                name
+            )));
+        }
+        let peer = peer
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| invalid_input("no peer address"))?;
+        let mut forwards = self.forwards.lock().unwrap();
+        let key = (name.to_string(), peer);
+        if forwards.contains_key(&key) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("topic {} is sent to {} already", name, peer),
+            ));
+        }
+        let rx = get_new_rx_of_message::<T>(name).map_err(invalid_input)?;
+        let fingerprint = fingerprint::<T>();
+        attach_message_schema(name, fingerprint).map_err(invalid_data)?;
+
+        // the local address keeps bridges sending the same topic to one peer apart
+        let callback_name = format!("bridge_{}_{}", self.local_addr()?, peer);
+        // the topic outlives the bridge, so the callback must not keep it alive
+        let bridge = Arc::downgrade(self);
+        let topic = name.to_string();
+        let mut forward = deferred(move |(seq, msg): &(u32, T)| {
+            let Some(bridge) = bridge.upgrade() else {
+                return;
+            };
+            let frame = encode_frame(&topic, *seq, fingerprint, as_bytes(msg));
+            // count before sending, the peer may read it before send_to returns.
+            // udp is lossy anyway, a failed send is just a lost message
+            bridge.sent_cnt.fetch_add(1, Ordering::AcqRel);
+            if bridge.socket.send_to(&frame, peer).is_err() {
+                bridge.sent_cnt.fetch_sub(1, Ordering::AcqRel);
+            }
+        });
+        // numbered on the sender's thread, so messages merged while the work queue falls
+        // behind are counted in the peer's lost_cnt
+        let mut seq = 0u32;
+        rx.register_callback(&callback_name, move |msg: &T| {
+            forward(&(seq, *msg));
+            seq = seq.wrapping_add(1);
+        });
+        forwards.insert(
+            key,
+            Box::new(move || rx.unregister_callback(&callback_name)),
+        );
+        Ok(())
+    }
+
+    /// stop sending topic `name` to `peer`, returns `NotFound` if it's not sent by `send_topic`.
+    /// a message published just before may still be sent.
+    pub fn stop_topic(&self, name: &str, peer: impl ToSocketAddrs) -> io::Result<()> {
+        let peer = peer
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| invalid_input("no peer address"))?;
+        let stop = self
+            .forwards
+            .lock()
+            .unwrap()
+            .remove(&(name.to_string(), peer))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("topic {} is not sent to {}", name, peer),
+                )
+            })?;
+        stop();
+        Ok(())
+    }
+
+    /// publish the frames of topic `name` from peers to the local topic of the same name.
+    pub fn recv_topic<T: Pod>(&self, name: &str) -> io::Result<()> {
+        self.recv_topic_as::<T>(name, name)
+    }
+
+    /// publish the frames of topic `name` from peers to the local topic `local_name`,
+    /// it's added if no one did. frames of another schema than `T` are counted in
+    /// `BridgeStats::mismatch_cnt` and dropped.
+    pub fn recv_topic_as<T: Pod>(&self, name: &str, local_name: &str) -> io::Result<()> {
+        let tx = match get_new_tx_of_message::<T>(local_name) {
+            Err(MessageError::NotFound { .. }) => {
+                add_message::<T>(local_name).and_then(|_| get_new_tx_of_message::<T>(local_name))
+            }
+            x => x,
+        }
+        .map_err(invalid_input)?;
+        let fingerprint = fingerprint::<T>();
+        attach_message_schema(local_name, fingerprint).map_err(invalid_data)?;
+        let topic = RemoteTopic {
+            republisher: Box::new(tx),
+            fingerprint,
+            last_seq: None,
+            mismatched: false,
+        };
+        self.remote_topics
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), topic);
+        Ok(())
+    }
+
+    /// receive frames and republish them on the current thread, until `stop` is called.
+    pub fn run(&self) -> io::Result<()> {
+        let mut buf = vec![0; MAX_DATAGRAM];
+        while self.running.load(Ordering::Acquire) {
+            let len = match self.socket.recv_from(&mut buf) {
+                Ok((len, _)) => len,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    continue
+                }
+                Err(e) => return Err(e),
+            };
+            match decode_frame(&buf[..len]) {
+                Some(frame) => self.republish(&frame),
+                None => {
+                    self.dropped_cnt.fetch_add(1, Ordering::AcqRel);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn republish(&self, frame: &Frame) {
+        let mut remote_topics = self.remote_topics.lock().unwrap();
+        match remote_topics.get_mut(frame.name) {
+            Some(topic) if topic.fingerprint != frame.fingerprint => {
+                self.mismatch_cnt.fetch_add(1, Ordering::AcqRel);
+                if !topic.mismatched {
+                    topic.mismatched = true;
+                    self.set_error(invalid_data(MessageError::SchemaMismatch {
+                        name: frame.name.to_string(),
+                        expected: topic.fingerprint,
+                        actual: frame.fingerprint,
+                    }));
+                }
+            }
+            Some(topic) if topic.republisher.accepts(frame.payload) => {
+                let lost = topic.lost_before(frame.seq);
+                self.lost_cnt.fetch_add(lost, Ordering::AcqRel);
+                // count before publishing, so a woken reader sees the stats of its message
+                self.received_cnt.fetch_add(1, Ordering::AcqRel);
+                topic.republisher.publish(frame.payload);
+            }
+            _ => {
+                self.dropped_cnt.fetch_add(1, Ordering::AcqRel);
+            }
+        }
+    }
+
+    /// run the receiving loop on a new SchedulePthread, an error stopping it is kept for `take_error`.
+    pub fn start(self: &Arc<Self>) -> Arc<SchedulePthread> {
+        let bridge = self.clone();
+        SchedulePthread::new_simple(Box::new(move |_| {
+            if let Err(e) = bridge.run() {
+                bridge.set_error(e);
+            }
+        }))
+    }
+
+    /// stop the receiving loop, it returns within POLL_INTERVAL.
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::Release);
+    }
+}
+
+impl Drop for Bridge {
+    fn drop(&mut self) {
+        for (_, stop) in self.forwards.get_mut().unwrap().drain() {
+            stop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msg::list_topics;
+
+    #[test]
+    fn test_frame() {
+        let frame = encode_frame("gyro", 3, 0x1234, &[1, 2, 3]);
+        assert_eq!(frame.len(), HEADER_LEN + 4 + 3);
+        let decoded = decode_frame(&frame).unwrap();
+        assert_eq!(
+            (
+                decoded.name,
+                decoded.seq,
+                decoded.fingerprint,
+                decoded.payload
+            ),
+            ("gyro", 3, 0x1234, &[1u8, 2, 3][..])
+        );
+
+        let mut other_version = frame.clone();
+        other_version[4] = FRAME_VERSION + 1;
+        assert!(decode_frame(&other_version).is_none());
+        assert!(decode_frame(&frame[..frame.len() - 1]).is_none());
+        assert!(decode_frame(&frame[..HEADER_LEN - 1]).is_none());
+    }
+
+    #[test]
+    fn test_bridge_loopback() {
+        let a = Bridge::bind("127.0.0.1:0").unwrap();
+        let b = Bridge::bind("127.0.0.1:0").unwrap();
+        let b_addr = b.local_addr().unwrap();
+
+        // both ends live in this process, so republish to another name instead of looping back
+        add_message::<[u32; 2]>("test_bridge_out").unwrap();
+        a.send_topic::<[u32; 2]>("test_bridge_out", b_addr).unwrap();
+        b.recv_topic_as::<[u32; 2]>("test_bridge_out", "test_bridge_in")
+            .unwrap();
+        let mut rx = get_new_rx_of_message::<[u32; 2]>("test_bridge_in").unwrap();
+        let b_thread = b.start();
+
+        let tx = get_new_tx_of_message::<[u32; 2]>("test_bridge_out").unwrap();
+        tx.send([1, 2]);
+        assert_eq!(rx.read_timeout(Duration::from_secs(1)).unwrap(), [1, 2]);
+        tx.send([3, 4]);
+        assert_eq!(rx.read_timeout(Duration::from_secs(1)).unwrap(), [3, 4]);
+        assert_eq!(a.stats().sent_cnt, 2);
+        assert_eq!(
+            a.send_topic::<[u32; 2]>("test_bridge_out", b_addr)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::AlreadyExists
+        );
+
+        // frames of another version, size or unknown topic are dropped
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let fp = fingerprint::<[u32; 2]>();
+        let mut frame = encode_frame("test_bridge_out", 0, fp, as_bytes(&[5u32, 6]));
+        frame[4] = FRAME_VERSION + 1;
+        socket.send_to(&frame, b_addr).unwrap();
+        let frame = encode_frame("test_bridge_out", 0, fp, as_bytes(&5u32));
+        socket.send_to(&frame, b_addr).unwrap();
+        let frame = encode_frame("test_bridge_unknown", 0, fp, as_bytes(&[5u32, 6]));
+        socket.send_to(&frame, b_addr).unwrap();
+        // the same size, but the peer's message type is another one
+        let other_fp = fingerprint::<[f32; 2]>();
+        let frame = encode_frame("test_bridge_out", 2, other_fp, as_bytes(&[5.0f32, 6.0]));
+        socket.send_to(&frame, b_addr).unwrap();
+        socket.send_to(&frame, b_addr).unwrap();
+        // seq 2 and 3 are lost
+        let frame = encode_frame("test_bridge_out", 4, fp, as_bytes(&[7u32, 8]));
+        socket.send_to(&frame, b_addr).unwrap();
+        assert_eq!(rx.read_timeout(Duration::from_secs(1)).unwrap(), [7, 8]);
+        assert_eq!(
+            b.stats(),
+            BridgeStats {
+                sent_cnt: 0,
+                received_cnt: 3,
+                dropped_cnt: 3,
+                lost_cnt: 2,
+                mismatch_cnt: 2
+            }
+        );
+        // the mismatch is reported once
+        let e = b.take_error().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains("schema fingerprint"));
+        assert!(b.take_error().is_none());
+        assert_eq!(
+            list_topics()
+                .iter()
+                .find(|x| x.name == "test_bridge_in")
+                .unwrap()
+                .fingerprint,
+            Some(fp)
+        );
+
+        // nothing is sent after stopped
+        a.stop_topic("test_bridge_out", b_addr).unwrap();
+        assert_eq!(
+            a.stop_topic("test_bridge_out", b_addr).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        tx.send([9, 10]);
+        assert!(rx.read_timeout(Duration::from_millis(200)).is_err());
+        assert_eq!(a.stats().sent_cnt, 2);
+
+        b.stop();
+        b_thread.join();
+        assert!(b.take_error().is_none());
+    }
+
+    #[test]
+    fn test_bridge_send_merged() {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        peer.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let a = Bridge::bind("127.0.0.1:0").unwrap();
+        add_message::<u32>("test_bridge_merged").unwrap();
+        a.send_topic::<u32>("test_bridge_merged", peer.local_addr().unwrap())
+            .unwrap();
+        let tx = get_new_tx_of_message::<u32>("test_bridge_merged").unwrap();
+
+        // the work queue falls behind, so the three messages are merged into the last one
+        let (block_tx, block_rx) = std::sync::mpsc::channel::<()>();
+        crate::work_queue::WORK_QUEUE.add(move || {
+            let _ = block_rx.recv();
+        });
+        for i in 0..3 {
+            tx.send(i);
+        }
+        block_tx.send(()).unwrap();
+        let mut buf = [0; 64];
+        let len = peer.recv(&mut buf).unwrap();
+        let frame = decode_frame(&buf[..len]).unwrap();
+        // the peer finds seq 0 and 1 lost
+        assert_eq!((frame.seq, frame.payload), (2, as_bytes(&2u32)));
+
+        // a dropped bridge stops forwarding, the topic doesn't keep it alive
+        let weak = Arc::downgrade(&a);
+        drop(a);
+        assert!(weak.upgrade().is_none());
+        tx.send(3);
+        peer.set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        assert!(peer.recv(&mut buf).is_err());
+    }
+}
@@ -1,128 +1,488 @@
 use std::{
-    collections::HashMap,
-    mem::MaybeUninit,
-    ptr::null_mut,
+    any::Any,
+    collections::{HashMap, VecDeque},
+    future::Future,
+    os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+    pin::Pin,
     sync::{
-        atomic::{AtomicBool, AtomicU32, Ordering},
-        Condvar, Mutex,
+        atomic::{AtomicBool, AtomicI64, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, OnceLock,
     },
+    task::{Context, Poll, Waker},
+    time::Duration,
 };
 
+use futures_core::Stream;
+use libc::c_long;
+
+use crate::{
+    executor::{sleep, Sleep},
+    hrt::{get_time_now, Timespec},
+    pthread::nanosleep,
+    seqlock::SeqLock,
+    shm::ShmTopic,
+    work_queue::WORK_QUEUE,
+};
+
+// when lock step drives the hrt clock, a timed wait never sleeps longer
+// than this on the condvar before checking the clock again.
+const WAIT_SLICE: Duration = Duration::from_millis(1);
+
+fn wait_slice(timeout: Duration) -> Duration {
+    if cfg!(feature = "lock_step_enabled") {
+        timeout.min(WAIT_SLICE)
+    } else {
+        timeout
+    }
+}
+
+// closing is not a write, so a lock free reader may miss the close wake between its check
+// and the futex wait. it sees the close within this slice then.
+const CLOSE_SLICE: Duration = Duration::from_millis(100);
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ReadError {
+    Timeout,
+    Closed, // every sender is dropped and all messages are read
+}
+
+/// runtime statistics of a channel, rates are in Hz and only count local senders.
+/// a rate is 0 until it could be measured, like before the second publish.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ChannelStats {
+    pub publish_cnt: u64,
+    pub last_publish: Option<Timespec>,
+    pub avg_rate: f64,
+    pub min_rate: f64,
+    pub max_rate: f64,
+    pub sender_cnt: u32,
+    pub receiver_cnt: u32,
+}
+
 pub struct Sender<T> {
-    parent: *mut Channel<T>,
+    parent: Arc<Channel<T>>,
 }
 
+/// a sender of a new channel without any receiver.
 impl<T> Default for Sender<T> {
     fn default() -> Self {
-        Self { parent: null_mut() }
+        Channel::create(0, None).0
     }
 }
 
 pub struct Receiver<T> {
-    parent: *mut Channel<T>,
+    parent: Arc<Channel<T>>,
     last_cnt: u32,
+    lost_cnt: u32,
+    event_fd: OnceLock<Arc<EventFd>>, // created on the first as_raw_fd
+    queue: Option<Arc<SubscriberQueue<T>>>, // set if created by with_queue
+    interval: Duration,               // minimum time between two reads, see set_interval
+    last_read: Option<Timespec>,
 }
 
+/// a receiver of a new channel without any sender, so it's already closed.
 impl<T> Default for Receiver<T> {
     fn default() -> Self {
-        Self {
-            parent: null_mut(),
-            last_cnt: 0,
-        }
+        Channel::create(0, None).1
     }
 }
 
-unsafe impl<T> Send for Sender<T> {}
-unsafe impl<T> Sync for Sender<T> {}
-unsafe impl<T> Send for Receiver<T> {}
-unsafe impl<T> Sync for Receiver<T> {}
-
 impl<T> Receiver<T>
 where
     T: Clone,
 {
-    pub fn read(&mut self) -> T {
-        let channel = unsafe { &mut *self.parent };
-        if self.last_cnt == channel.cnt {
+    /// block until a new message arrives, return `ReadError::Closed` once the channel
+    /// is closed(see `is_closed`) and there is nothing left to read.
+    pub fn read(&mut self) -> Result<T, ReadError> {
+        self.read_with_timestamp().map(|x| x.0)
+    }
+
+    /// like read, also return the hrt time when the message was sent.
+    pub fn read_with_timestamp(&mut self) -> Result<(T, Timespec), ReadError> {
+        let channel = self.parent.clone();
+        loop {
+            if let Some(msg) = self.try_read_stamped() {
+                let timestamp = msg.timestamp();
+                return Ok((msg.data, timestamp));
+            }
+            if !self.has_unread() && channel.is_closed() {
+                return Err(ReadError::Closed);
+            }
             // if no new msg, then wait for update
-            channel.wait_for_update();
+            self.wait_for_update(None);
+        }
+    }
+
+    /// block until a new message arrives or `timeout` escaped on the hrt clock.
+    pub fn read_timeout(&mut self, timeout: Duration) -> Result<T, ReadError> {
+        self.read_until(get_time_now() + Timespec::from(timeout))
+    }
+
+    /// block until a new message arrives or the hrt clock reaches `deadline`.
+    pub fn read_until(&mut self, deadline: Timespec) -> Result<T, ReadError> {
+        let channel = self.parent.clone();
+        loop {
+            if let Some(data) = self.try_read() {
+                return Ok(data);
+            }
+            if !self.has_unread() && channel.is_closed() {
+                return Err(ReadError::Closed);
+            }
+            let now = get_time_now();
+            if now >= deadline {
+                return Err(ReadError::Timeout);
+            }
+            let remain = Duration::from_nanos((deadline - now).to_nano() as u64);
+            self.wait_for_update(Some(remain));
         }
-        let (cnt, data) = channel.read();
-        self.last_cnt = cnt;
-        data
     }
 
     pub fn try_read(&mut self) -> Option<T> {
-        let channel = unsafe { &mut *self.parent };
-        let (cnt, data) = channel.read();
-        let updated = self.last_cnt != cnt;
+        self.try_read_stamped().map(|msg| msg.data)
+    }
 
-        if updated {
-            Some(data)
-        } else {
+    /// like try_read, but a new message older than `max_age` is dropped and None is returned,
+    /// so a stale sample is never used.
+    pub fn try_read_fresh(&mut self, max_age: Duration) -> Option<T> {
+        let msg = self.try_read_stamped()?;
+        if (get_time_now() - msg.timestamp()).to_nano() > max_age.as_nanos() as i64 {
             None
+        } else {
+            Some(msg.data)
         }
     }
 
+    fn try_read_stamped(&mut self) -> Option<Stamped<T>> {
+        if self.throttle_remain().is_some() {
+            return None;
+        }
+        let (cnt, data, lost) = match &self.queue {
+            Some(queue) => {
+                let (cnt, data) = queue.list.lock().unwrap().pop_front()?;
+                // messages missing between the last read one and this one are lost.
+                // racing lock free writers may queue a message after a newer one, the cnt
+                // goes backwards then and nothing is lost
+                let gap = cnt.wrapping_sub(self.last_cnt);
+                if gap as i32 > 0 {
+                    (cnt, data, gap - 1)
+                } else {
+                    (self.last_cnt, data, 0)
+                }
+            }
+            None => self.parent.read(self.last_cnt)?,
+        };
+        self.last_cnt = cnt;
+        self.lost_cnt = self.lost_cnt.saturating_add(lost);
+        if !self.interval.is_zero() {
+            self.last_read = Some(get_time_now());
+        }
+        if let Some(event_fd) = self.event_fd.get() {
+            // keep the fd readable while there are still unread messages in the queue
+            event_fd.clear();
+            if self.has_unread() {
+                event_fd.notify();
+            }
+        }
+        Some(data)
+    }
+
+    /// a new receiver of the same channel with its own fifo of the latest `depth` messages,
+    /// so it reads every message in order even if the channel only keeps the latest one.
+    /// for a shm channel, only messages sent in this process are queued.
+    pub fn with_queue(&self, depth: usize) -> Receiver<T> {
+        assert!(depth > 0, "queue depth should be greater than 0");
+        self.parent.subscribe(depth)
+    }
+
+    // return early on a new message, the end of the throttle interval, or the timeout
+    fn wait_for_update(&self, timeout: Option<Duration>) {
+        if self.has_unread() {
+            if let Some(remain) = self.throttle_remain() {
+                // nanosleep follows the hrt clock, even if lock step drives it
+                let remain = timeout.map_or(remain, |x| x.min(remain));
+                nanosleep(remain.as_nanos().min(999_999_999) as c_long);
+            }
+            return;
+        }
+        // the hrt clock may be driven by lock step, so wait in slices with a timeout
+        let timeout = timeout.map(wait_slice);
+        match (&self.queue, timeout) {
+            (Some(queue), _) => queue.wait(&self.parent, timeout),
+            (None, None) => self.parent.wait_for_update(self.last_cnt),
+            (None, Some(timeout)) => self.parent.wait_for_update_timeout(self.last_cnt, timeout),
+        }
+    }
+
+    /// wait for a new message in async code, see `read`.
+    pub fn recv(&mut self) -> Recv<'_, T> {
+        Recv {
+            rx: self,
+            notifier: None,
+            throttle: None,
+        }
+    }
+
+    /// turn the receiver into a `Stream` of new messages, which ends once the channel is closed.
+    pub fn into_stream(self) -> RecvStream<T> {
+        RecvStream {
+            rx: self,
+            notifier: None,
+            throttle: None,
+        }
+    }
+
+    fn poll_recv(
+        &mut self,
+        notifier: &mut Option<Arc<WakerNotifier>>,
+        throttle: &mut Option<Sleep>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<T, ReadError>> {
+        let notifier = notifier.get_or_insert_with(|| {
+            let notifier = Arc::new(WakerNotifier {
+                waker: Mutex::new(None),
+            });
+            self.parent.register_notifier(notifier.clone());
+            notifier
+        });
+        // store the waker before checking, so a message sent in between always wakes the task
+        *notifier.waker.lock().unwrap() = Some(cx.waker().clone());
+
+        if let Some(data) = self.try_read() {
+            *throttle = None;
+            return Poll::Ready(Ok(data));
+        }
+        if !self.has_unread() {
+            if self.is_closed() {
+                return Poll::Ready(Err(ReadError::Closed));
+            }
+        } else if let Some(remain) = self.throttle_remain() {
+            // no message wakes the task when the interval is over, a timer does
+            let timer = throttle.get_or_insert_with(|| sleep(remain));
+            if Pin::new(timer).poll(cx).is_ready() {
+                *throttle = None;
+                cx.waker().wake_by_ref();
+            }
+        }
+        Poll::Pending
+    }
+
+    /// number of queued messages that were overwritten before this receiver read them.
+    /// always 0 for a latest-value receiver of a latest-value channel.
+    pub fn lost_cnt(&self) -> u32 {
+        self.lost_cnt
+    }
+
+    /// call `callback` on the sender's thread for every message sent from now on.
+    /// if another sender is running the callback, that thread calls it with the message instead,
+    /// so the send never blocks on it.
+    /// a message the callback sends to this channel itself is not passed to it again,
+    /// receivers and other callbacks still get it.
     pub fn register_callback<F>(&self, name: &str, callback: F)
     where
-        F: FnMut(&T) + 'static,
+        F: FnMut(&T) + Send + 'static,
+    {
+        self.parent.register_callback(name, callback);
+    }
+
+    /// like register_callback, but the callback runs on the work queue thread instead of the
+    /// sender's thread, so a slow callback never delays the sender.
+    /// if the callback falls behind, only the latest message waits for it.
+    pub fn register_deferred_callback<F>(&self, name: &str, callback: F)
+    where
+        F: FnMut(&T) + Send + 'static,
+        T: Send + 'static,
+    {
+        self.register_callback(name, deferred(callback));
+    }
+
+    /// like register_callback, but the callback is called at most once per `interval`,
+    /// messages in between are skipped.
+    pub fn register_callback_with_interval<F>(
+        &self,
+        name: &str,
+        interval: Duration,
+        mut callback: F,
+    ) where
+        F: FnMut(&T) + Send + 'static,
+    {
+        let mut last_call: Option<Timespec> = None;
+        self.register_callback(name, move |msg: &T| {
+            let now = get_time_now();
+            if last_call.is_some_and(|x| (now - x).to_nano() < interval.as_nanos() as i64) {
+                return;
+            }
+            last_call = Some(now);
+            callback(msg);
+        });
+    }
+
+    /// like register_callback, but the callback is only called for messages `filter` accepts.
+    pub fn register_callback_with_filter<P, F>(&self, name: &str, mut filter: P, mut callback: F)
+    where
+        P: FnMut(&T) -> bool + Send + 'static,
+        F: FnMut(&T) + Send + 'static,
     {
-        let channel = unsafe { &mut *self.parent };
-        channel.register_callback(name, callback);
+        self.register_callback(name, move |msg: &T| {
+            if filter(msg) {
+                callback(msg);
+            }
+        });
     }
 
     pub fn unregister_callback(&self, name: &str) {
-        let channel = unsafe { &mut *self.parent };
-        channel.unregister_callback(name);
+        self.parent.unregister_callback(name);
+    }
+
+    pub fn stats(&self) -> ChannelStats {
+        self.parent.stats()
     }
 }
 
+/// the clone of a receiver with queue gets its own empty queue of the same depth.
 impl<T> Clone for Receiver<T> {
     fn clone(&self) -> Self {
-        unsafe {
-            (&mut *(self.parent))
-                .receiver_cnt
-                .fetch_add(1, Ordering::AcqRel)
-        };
+        if let Some(queue) = &self.queue {
+            let mut rx = self.parent.subscribe(queue.depth);
+            rx.interval = self.interval;
+            return rx;
+        }
+        self.parent.receiver_cnt.fetch_add(1, Ordering::AcqRel);
         Self {
             parent: self.parent.clone(),
-            last_cnt: self.last_cnt.clone(),
+            last_cnt: self.last_cnt,
+            lost_cnt: 0,
+            event_fd: OnceLock::new(),
+            queue: None,
+            interval: self.interval,
+            last_read: None,
         }
     }
 }
 
 impl<T> Drop for Receiver<T> {
     fn drop(&mut self) {
-        if self.parent == null_mut() {
-            return;
+        if let Some(event_fd) = self.event_fd.get() {
+            let notifier: Arc<dyn Notify> = event_fd.clone();
+            self.parent.unregister_notifier(&notifier);
         }
-        let channel = unsafe { &mut *(self.parent) };
-        if channel.receiver_cnt.fetch_sub(1, Ordering::AcqRel) == 1 {
-            if channel.destoryed.swap(true, Ordering::AcqRel) == true {
-                drop(unsafe { Box::from_raw(self.parent) });
+        if let Some(queue) = &self.queue {
+            self.parent.unsubscribe(queue);
+        }
+        self.parent.receiver_cnt.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl<T> Receiver<T> {
+    /// call `f` with the latest message of the channel without copying it,
+    /// return None if nothing is sent yet. the message is not marked as read.
+    /// senders are blocked while `f` runs, so keep it short and never send to this channel in it.
+    pub fn with_latest<R, F>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&T) -> R,
+    {
+        self.parent.with_latest(f)
+    }
+
+    /// true if anything was ever sent to the channel(or it has an initial value),
+    /// unlike try_read it doesn't care whether this receiver has read it.
+    pub fn has_data(&self) -> bool {
+        self.parent.has_data()
+    }
+
+    /// true if every sender of the channel is dropped or it's closed by `msg::remove_message`,
+    /// no new message will arrive.
+    /// a shm channel never closes by dropping senders, since senders in other processes are unknown.
+    pub fn is_closed(&self) -> bool {
+        self.parent.is_closed()
+    }
+
+    /// how long ago the latest message of the channel was sent, None if nothing is sent yet.
+    pub fn age(&self) -> Option<Duration> {
+        let timestamp = self.parent.latest_timestamp()?;
+        let age = (get_time_now() - timestamp).to_nano();
+        Some(Duration::from_nanos(age.max(0) as u64))
+    }
+
+    fn event_fd(&self) -> &Arc<EventFd> {
+        self.event_fd.get_or_init(|| {
+            let event_fd = Arc::new(EventFd::new());
+            self.parent.register_notifier(event_fd.clone());
+            if self.has_unread() {
+                event_fd.notify();
             }
+            event_fd
+        })
+    }
+
+    /// read/try_read return a new message at most once per `interval`, like `orb_set_interval`
+    /// of uORB. messages in between are skipped on a latest-value receiver. 0 disables it.
+    /// `ReceiverSet` and the eventfd are not throttled.
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    // time left until the next read is allowed, None if it's allowed now
+    fn throttle_remain(&self) -> Option<Duration> {
+        let passed = (get_time_now() - self.last_read?).to_nano();
+        let interval = self.interval.as_nanos() as i64;
+        (passed < interval).then(|| Duration::from_nanos((interval - passed) as u64))
+    }
+
+    fn has_unread(&self) -> bool {
+        match &self.queue {
+            Some(queue) => !queue.list.lock().unwrap().is_empty(),
+            None => self.parent.cnt() != self.last_cnt,
         }
     }
 }
 
+/// the fd is readable while this receiver has unread messages, so it could be registered
+/// into a `polling::Poller` together with sockets and serial ports.
+impl<T> AsRawFd for Receiver<T> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.event_fd().fd.as_raw_fd()
+    }
+}
+
+impl<T> AsFd for Receiver<T> {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.event_fd().fd.as_fd()
+    }
+}
+
 impl<T> Sender<T>
 where
     T: Clone,
 {
     pub fn send(&self, data: T) {
-        let channel = unsafe { &mut *(self.parent) };
-        channel.write(data);
+        self.parent.write(data);
+    }
+
+    pub fn stats(&self) -> ChannelStats {
+        self.parent.stats()
+    }
+}
+
+impl<T> Sender<T> {
+    // close the channel even if other senders are alive, their messages are dropped from now on
+    pub(crate) fn close(&self) {
+        if !self.parent.closed.swap(true, Ordering::AcqRel) {
+            self.parent.wake_closed();
+        }
+    }
+}
+
+impl<T> Sender<Arc<T>> {
+    /// wrap `data` in an Arc and send it, so receivers share one message instead of copying it.
+    /// `T` doesn't need to be Clone.
+    pub fn send_shared(&self, data: T) {
+        self.send(Arc::new(data));
     }
 }
 
 impl<T> Clone for Sender<T> {
     fn clone(&self) -> Self {
-        unsafe {
-            (&mut *(self.parent))
-                .sender_cnt
-                .fetch_add(1, Ordering::AcqRel)
-        };
+        self.parent.sender_cnt.fetch_add(1, Ordering::AcqRel);
         Self {
             parent: self.parent.clone(),
         }
@@ -131,168 +491,1085 @@ impl<T> Clone for Sender<T> {
 
 impl<T> Drop for Sender<T> {
     fn drop(&mut self) {
-        if self.parent == null_mut() {
-            return;
+        if self.parent.sender_cnt.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.parent.wake_closed();
         }
-        let channel = unsafe { &mut *(self.parent) };
-        if channel.sender_cnt.fetch_sub(1, Ordering::AcqRel) == 1 {
-            if channel.destoryed.swap(true, Ordering::AcqRel) == true {
-                drop(unsafe { Box::from_raw(self.parent) });
-            }
+    }
+}
+
+// the message storage of a locked channel
+struct Slot<T> {
+    data: Option<Stamped<T>>, // None until the first message
+    queue: VecDeque<Stamped<T>>,
+}
+
+// the fifo of a receiver created by `Receiver::with_queue`, filled by the writer
+struct SubscriberQueue<T> {
+    list: Mutex<VecDeque<(u32, Stamped<T>)>>, // with the cnt of each message
+    depth: usize,
+    condvar: Condvar,
+}
+
+impl<T> SubscriberQueue<T> {
+    fn push(&self, cnt: u32, msg: Stamped<T>) {
+        let mut list = self.list.lock().unwrap();
+        if list.len() == self.depth {
+            list.pop_front();
+        }
+        list.push_back((cnt, msg));
+        self.condvar.notify_all();
+    }
+
+    fn wake(&self) {
+        let _a = self.list.lock().unwrap();
+        self.condvar.notify_all();
+    }
+
+    fn wait(&self, channel: &Channel<T>, timeout: Option<Duration>) {
+        let list = self.list.lock().unwrap();
+        let cond = |x: &mut VecDeque<_>| x.is_empty() && !channel.is_closed();
+        match timeout {
+            Some(timeout) => drop(
+                self.condvar
+                    .wait_timeout_while(list, timeout, cond)
+                    .unwrap(),
+            ),
+            None => drop(self.condvar.wait_while(list, cond).unwrap()),
         }
     }
 }
 
 pub struct Channel<T> {
-    data: MaybeUninit<T>,
-    callbacks: Mutex<HashMap<String, Box<dyn FnMut(&T)>>>,
-    cnt: u32,
-    lock: Mutex<bool>, // this lock protect data,cnt
+    queue_depth: usize,              // 0 means only the latest message is kept in data
+    seqlock: Option<SeqLockSlot<T>>, // if set, lock/cnt are unused and the writer never locks
+    callbacks: Mutex<HashMap<String, Arc<Callback<T>>>>,
+    has_callbacks: AtomicBool,
+    notifiers: Mutex<Vec<Arc<dyn Notify>>>, // woken after every write, used by ReceiverSet
+    has_notifiers: AtomicBool,
+    notify_pending: AtomicBool, // a write to notify, set before trying the notifiers lock
+    subscribers: Mutex<Vec<Arc<SubscriberQueue<T>>>>,
+    has_subscribers: AtomicBool,
+    cnt: AtomicU32, // only changed with the lock held
+    lock: Mutex<Slot<T>>,
     condvar: Condvar,
+    waiters: AtomicU32,
+    publish_stats: PublishStats,
     sender_cnt: AtomicU32,
     receiver_cnt: AtomicU32,
-    destoryed: AtomicBool,
+    closed: AtomicBool, // set by Sender::close
 }
 
-unsafe impl<T> Send for Channel<T> {}
-unsafe impl<T> Sync for Channel<T> {}
+// messages are moved between threads, and shared by with_latest and the callbacks
+unsafe impl<T: Send> Send for Channel<T> {}
+unsafe impl<T: Send + Sync> Sync for Channel<T> {}
 
-impl<T> Channel<T>
-where
-    T: Sized + Clone,
-{
-    pub fn new() -> (Sender<T>, Receiver<T>) {
-        let channel = Box::new(Channel {
-            data: MaybeUninit::zeroed(),
-            callbacks: Mutex::new(HashMap::new()),
-            cnt: 0,
-            lock: Mutex::new(false),
-            condvar: Condvar::new(),
-            sender_cnt: AtomicU32::new(1),
-            receiver_cnt: AtomicU32::new(1),
-            destoryed: AtomicBool::new(false),
-        });
+// the time of a publish that never happened
+const UNSET: i64 = i64::MIN;
 
-        let channel_ptr = Box::into_raw(channel);
-        let tx = Sender {
-            parent: channel_ptr,
-        };
-        let rx = Receiver {
-            parent: channel_ptr,
-            last_cnt: 0,
-        };
+// all in nanoseconds of the hrt clock, atomics so the lock free writer could update them
+struct PublishStats {
+    publish_cnt: AtomicU64,
+    first_publish: AtomicI64,
+    last_publish: AtomicI64,
+    min_interval: AtomicI64,
+    max_interval: AtomicI64,
+}
 
-        (tx, rx)
+impl PublishStats {
+    fn new() -> Self {
+        Self {
+            publish_cnt: AtomicU64::new(0),
+            first_publish: AtomicI64::new(UNSET),
+            last_publish: AtomicI64::new(UNSET),
+            min_interval: AtomicI64::new(i64::MAX),
+            max_interval: AtomicI64::new(0),
+        }
     }
 
-    fn register_callback<F>(&mut self, name: &str, callback: F)
-    where
-        F: FnMut(&T) + 'static,
-    {
-        self.callbacks
-            .lock()
-            .unwrap()
-            .insert(name.to_string(), Box::new(callback));
+    fn record(&self, now: Timespec) {
+        let now = now.to_nano();
+        let last = self.last_publish.swap(now, Ordering::AcqRel);
+        self.publish_cnt.fetch_add(1, Ordering::AcqRel);
+        if last == UNSET {
+            let _ = self.first_publish.compare_exchange(
+                UNSET,
+                now,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            );
+        } else {
+            // racing senders may swap in their time out of order
+            let interval = (now - last).max(0);
+            self.min_interval.fetch_min(interval, Ordering::AcqRel);
+            self.max_interval.fetch_max(interval, Ordering::AcqRel);
+        }
+    }
+
+    // per second for an interval in nanoseconds, 0 if it is not measured
+    fn rate(cnt: u64, interval: i64) -> f64 {
+        if interval > 0 && interval != i64::MAX {
+            cnt as f64 * 1e9 / interval as f64
+        } else {
+            0.0
+        }
     }
+}
+
+// a message with the hrt time when it was sent, in nanoseconds
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(crate) struct Stamped<T> {
+    timestamp: i64,
+    data: T,
+}
 
-    fn unregister_callback(&mut self, name: &str) {
-        self.callbacks.lock().unwrap().remove(&name.to_string());
+impl<T> Stamped<T> {
+    fn timestamp(&self) -> Timespec {
+        Timespec::from_nano(self.timestamp)
     }
+}
+
+// where the seqlock of a lock free channel lives
+pub(crate) enum SeqLockSlot<T> {
+    Local(SeqLock<Stamped<T>>),
+    Shared(ShmTopic<T>),
+}
 
-    fn write(&mut self, msg: T) {
-        for (_, callback) in self.callbacks.lock().unwrap().iter_mut() {
-            callback(&msg);
+impl<T> SeqLockSlot<T> {
+    fn get(&self) -> &SeqLock<Stamped<T>> {
+        match self {
+            SeqLockSlot::Local(seqlock) => seqlock,
+            SeqLockSlot::Shared(shm) => shm.seqlock(),
         }
+    }
+}
 
-        {
-            let _a = self.lock.lock().unwrap();
-            self.data.write(msg);
-            self.cnt += 1;
+impl<T> Channel<T> {
+    fn cnt(&self) -> u32 {
+        match &self.seqlock {
+            Some(seqlock) => seqlock.get().cnt(),
+            None => self.cnt.load(Ordering::Acquire),
         }
-        self.condvar.notify_all();
     }
 
-    fn read(&self) -> (u32, T)
-    where
-        T: Clone,
-    {
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+            || (!matches!(self.seqlock, Some(SeqLockSlot::Shared(_)))
+                && self.sender_cnt.load(Ordering::Acquire) == 0)
+    }
+
+    // the channel is closed, wake the blocked readers
+    fn wake_closed(&self) {
+        // notify under the lock, so a reader between its check and wait never misses it
         let _a = self.lock.lock().unwrap();
-        (self.cnt, unsafe { self.data.assume_init_ref().clone() })
+        self.condvar.notify_all();
+        for queue in self.subscribers.lock().unwrap().iter() {
+            queue.wake();
+        }
+        drop(_a);
+        if let Some(seqlock) = &self.seqlock {
+            seqlock.get().wake();
+        }
+        // async readers and pollers check is_closed when woken
+        self.notify_notifiers();
     }
 
-    fn wait_for_update(&self) {
-        drop(self.condvar.wait(self.lock.lock().unwrap()).unwrap());
+    fn latest_timestamp(&self) -> Option<Timespec> {
+        if let Some(seqlock) = &self.seqlock {
+            return seqlock.get().read().map(|(_, msg)| msg.timestamp());
+        }
+
+        let slot = self.lock.lock().unwrap();
+        if self.queue_depth == 0 {
+            slot.data.as_ref().map(|msg| msg.timestamp())
+        } else {
+            slot.queue.back().map(|msg| msg.timestamp())
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
+    // not cnt != 0, cnt wraps around to 0
+    fn has_data(&self) -> bool {
+        match &self.seqlock {
+            Some(seqlock) => seqlock.get().written(),
+            None => self.latest_timestamp().is_some(),
+        }
+    }
 
-    use super::*;
+    fn with_latest<R, F>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&T) -> R,
+    {
+        if let Some(seqlock) = &self.seqlock {
+            // the data of a seqlock is Copy and may change at any time, so borrow a copy of it
+            let (_, msg) = seqlock.get().read()?;
+            return Some(f(&msg.data));
+        }
 
-    #[derive(Debug, Default, Clone, Copy)]
-    struct TestStruct {
-        x: u32,
-        y: u32,
-        z: u32,
+        let slot = self.lock.lock().unwrap();
+        let msg = if self.queue_depth == 0 {
+            slot.data.as_ref()
+        } else {
+            slot.queue.back()
+        }?;
+        Some(f(&msg.data))
     }
 
-    #[test]
-    fn test_basic_rxtx() {
-        let (tx, mut rx) = Channel::<TestStruct>::new();
+    fn stats(&self) -> ChannelStats {
+        let stats = &self.publish_stats;
+        let publish_cnt = stats.publish_cnt.load(Ordering::Acquire);
+        let last_publish = stats.last_publish.load(Ordering::Acquire);
+        let mut ret = ChannelStats {
+            publish_cnt,
+            last_publish: None,
+            avg_rate: 0.0,
+            min_rate: 0.0,
+            max_rate: 0.0,
+            sender_cnt: self.sender_cnt.load(Ordering::Acquire),
+            receiver_cnt: self.receiver_cnt.load(Ordering::Acquire),
+        };
+        if last_publish != UNSET {
+            ret.last_publish = Some(Timespec::from_nano(last_publish));
+        }
+        let first_publish = stats.first_publish.load(Ordering::Acquire);
+        if publish_cnt >= 2 && first_publish != UNSET && last_publish != UNSET {
+            ret.avg_rate = PublishStats::rate(publish_cnt - 1, last_publish - first_publish);
+            ret.min_rate = PublishStats::rate(1, stats.max_interval.load(Ordering::Acquire));
+            ret.max_rate = PublishStats::rate(1, stats.min_interval.load(Ordering::Acquire));
+        }
+        ret
+    }
 
-        tx.send(TestStruct::default());
-        rx.read();
-        assert_eq!(rx.last_cnt, 1);
+    fn register_notifier(&self, notifier: Arc<dyn Notify>) {
+        let mut notifiers = self.notifiers.lock().unwrap();
+        notifiers.push(notifier);
+        self.has_notifiers.store(true, Ordering::Release);
+        drop(notifiers);
+        self.drain_notify();
+    }
 
-        let try_result = rx.try_read();
-        assert!(try_result.is_none());
+    fn unregister_notifier(&self, notifier: &Arc<dyn Notify>) {
+        let mut notifiers = self.notifiers.lock().unwrap();
+        notifiers.retain(|x| Arc::as_ptr(x) as *const () != Arc::as_ptr(notifier) as *const ());
+        self.has_notifiers
+            .store(!notifiers.is_empty(), Ordering::Release);
+        drop(notifiers);
+        self.drain_notify();
     }
 
-    #[test]
-    fn test_channel_drop() {
-        let (tx, mut rx) = Channel::<TestStruct>::new();
-        {
-            let rx2 = rx.clone();
-            assert_eq!(
-                unsafe { (&*(rx.parent)).receiver_cnt.load(Ordering::Relaxed) },
-                2
-            );
+    fn notify_notifiers(&self) {
+        if self.has_notifiers.load(Ordering::Acquire) {
+            self.notify_pending.store(true, Ordering::SeqCst);
+            self.drain_notify();
         }
-        assert_eq!(
-            unsafe { (&*(rx.parent)).receiver_cnt.load(Ordering::Relaxed) },
-            1
-        );
+    }
 
-        drop(tx);
-        assert_eq!(
-            unsafe { (&*(rx.parent)).sender_cnt.load(Ordering::Relaxed) },
-            0
-        );
-        assert_eq!(
-            unsafe { (&*(rx.parent)).destoryed.load(Ordering::Relaxed) },
-            true
-        );
+    // the writer never blocks on the notifiers lock. if someone holds it, the holder
+    // notifies for the writer after unlocking.
+    fn drain_notify(&self) {
+        while self.notify_pending.load(Ordering::SeqCst) {
+            let Ok(notifiers) = self.notifiers.try_lock() else {
+                return;
+            };
+            if self.notify_pending.swap(false, Ordering::SeqCst) {
+                for notifier in notifiers.iter() {
+                    notifier.notify();
+                }
+            }
+        }
     }
 
-    #[test]
-    fn test_block_read() {
-        let (tx, mut rx) = Channel::<TestStruct>::new();
-        let start_time = std::time::SystemTime::now();
-        std::thread::spawn(move || {
-            std::thread::sleep(std::time::Duration::from_secs(5));
-            tx.send(TestStruct::default());
+    pub(crate) fn create(
+        queue_depth: usize,
+        seqlock: Option<SeqLockSlot<T>>,
+    ) -> (Sender<T>, Receiver<T>) {
+        let channel = Arc::new(Channel {
+            queue_depth,
+            seqlock,
+            callbacks: Mutex::new(HashMap::new()),
+            has_callbacks: AtomicBool::new(false),
+            notifiers: Mutex::new(Vec::new()),
+            has_notifiers: AtomicBool::new(false),
+            notify_pending: AtomicBool::new(false),
+            subscribers: Mutex::new(Vec::new()),
+            has_subscribers: AtomicBool::new(false),
+            cnt: AtomicU32::new(0),
+            lock: Mutex::new(Slot {
+                data: None,
+                queue: VecDeque::with_capacity(queue_depth),
+            }),
+            condvar: Condvar::new(),
+            waiters: AtomicU32::new(0),
+            publish_stats: PublishStats::new(),
+            sender_cnt: AtomicU32::new(1),
+            receiver_cnt: AtomicU32::new(1),
+            closed: AtomicBool::new(false),
         });
-        rx.read();
 
-        let escape = start_time.elapsed().unwrap();
+        let tx = Sender {
+            parent: channel.clone(),
+        };
+        let rx = Receiver {
+            parent: channel,
+            last_cnt: 0,
+            lost_cnt: 0,
+            event_fd: OnceLock::new(),
+            queue: None,
+            interval: Duration::ZERO,
+            last_read: None,
+        };
 
-        assert!(escape.as_secs() >= 5);
+        (tx, rx)
     }
 
-    #[test]
-    fn test_channel_callback() {
-        fn test_func(msg: &TestStruct) {
+    fn subscribe(self: &Arc<Self>, depth: usize) -> Receiver<T> {
+        let queue = Arc::new(SubscriberQueue {
+            list: Mutex::new(VecDeque::with_capacity(depth)),
+            depth,
+            condvar: Condvar::new(),
+        });
+        // the locked writer pushes with the lock held, so no message is missed or counted as lost
+        let _a = self.lock.lock().unwrap();
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.push(queue.clone());
+        self.has_subscribers.store(true, Ordering::Release);
+        self.receiver_cnt.fetch_add(1, Ordering::AcqRel);
+        Receiver {
+            parent: self.clone(),
+            last_cnt: self.cnt(),
+            lost_cnt: 0,
+            event_fd: OnceLock::new(),
+            queue: Some(queue),
+            interval: Duration::ZERO,
+            last_read: None,
+        }
+    }
+
+    fn unsubscribe(&self, queue: &Arc<SubscriberQueue<T>>) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|x| !Arc::ptr_eq(x, queue));
+        self.has_subscribers
+            .store(!subscribers.is_empty(), Ordering::Release);
+    }
+}
+
+impl<T> Channel<T>
+where
+    T: Sized + Clone,
+{
+    pub fn new() -> (Sender<T>, Receiver<T>) {
+        Self::create(0, None)
+    }
+
+    /// create a channel which keeps the latest `depth` messages in a fifo,
+    /// so every receiver could read them in order instead of only the latest one.
+    pub fn with_queue(depth: usize) -> (Sender<T>, Receiver<T>) {
+        assert!(depth > 0, "queue depth should be greater than 0");
+        Self::create(depth, None)
+    }
+
+    /// like new, but receivers read `value` as the first message until something is sent.
+    /// it's not counted in the statistics.
+    pub fn with_initial(value: T) -> (Sender<T>, Receiver<T>) {
+        let (tx, rx) = Self::create(0, None);
+        let channel = &tx.parent;
+        let mut slot = channel.lock.lock().unwrap();
+        slot.data = Some(Stamped {
+            timestamp: get_time_now().to_nano(),
+            data: value,
+        });
+        channel.cnt.store(1, Ordering::Release);
+        drop(slot);
+        (tx, rx)
+    }
+
+    fn register_callback<F>(&self, name: &str, callback: F)
+    where
+        F: FnMut(&T) + Send + 'static,
+    {
+        let mut callbacks = self.callbacks.lock().unwrap();
+        callbacks.insert(name.to_string(), Arc::new(Callback::new(callback)));
+        self.has_callbacks.store(true, Ordering::Release);
+    }
+
+    fn unregister_callback(&self, name: &str) {
+        let mut callbacks = self.callbacks.lock().unwrap();
+        callbacks.remove(&name.to_string());
+        self.has_callbacks
+            .store(!callbacks.is_empty(), Ordering::Release);
+    }
+
+    fn write(&self, msg: T) {
+        if self.closed.load(Ordering::Acquire) {
+            return;
+        }
+        let now = get_time_now();
+        self.publish_stats.record(now);
+
+        // skip the callbacks lock when nobody registered, so the lock free writer never blocks
+        if self.has_callbacks.load(Ordering::Acquire) {
+            // run callbacks without the map lock, so a callback could send to this channel
+            // or (un)register callbacks
+            let callbacks: Vec<_> = self.callbacks.lock().unwrap().values().cloned().collect();
+            for callback in callbacks {
+                callback.call(&msg);
+            }
+        }
+
+        let msg = Stamped {
+            timestamp: now.to_nano(),
+            data: msg,
+        };
+        let queued = self
+            .has_subscribers
+            .load(Ordering::Acquire)
+            .then(|| msg.clone());
+
+        if let Some(seqlock) = &self.seqlock {
+            let cnt = seqlock.get().write(msg);
+            if let Some(msg) = queued {
+                self.push_subscribers(cnt, msg);
+            }
+            seqlock.get().wake();
+        } else {
+            let mut slot = self.lock.lock().unwrap();
+            if self.queue_depth == 0 {
+                slot.data = Some(msg);
+            } else {
+                if slot.queue.len() == self.queue_depth {
+                    slot.queue.pop_front();
+                }
+                slot.queue.push_back(msg);
+            }
+            let cnt = self.cnt.fetch_add(1, Ordering::AcqRel) + 1;
+            if let Some(msg) = queued {
+                self.push_subscribers(cnt, msg);
+            }
+        }
+        if self.waiters.load(Ordering::SeqCst) > 0 {
+            self.condvar.notify_all();
+        }
+        self.notify_notifiers();
+    }
+
+    fn push_subscribers(&self, cnt: u32, msg: Stamped<T>) {
+        for queue in self.subscribers.lock().unwrap().iter() {
+            queue.push(cnt, msg.clone());
+        }
+    }
+
+    /// return the message after `last_cnt` with its cnt and the number of
+    /// messages lost to queue overflow, or None if there is no new message.
+    fn read(&self, last_cnt: u32) -> Option<(u32, Stamped<T>, u32)>
+    where
+        T: Clone,
+    {
+        if let Some(seqlock) = &self.seqlock {
+            let (cnt, data) = seqlock.get().read()?;
+            return if cnt == last_cnt {
+                None
+            } else {
+                Some((cnt, data, 0))
+            };
+        }
+
+        let slot = self.lock.lock().unwrap();
+        let cnt = self.cnt();
+        if cnt == last_cnt {
+            return None;
+        }
+        if self.queue_depth == 0 {
+            return slot.data.clone().map(|data| (cnt, data, 0));
+        }
+
+        let oldest = cnt - slot.queue.len() as u32 + 1;
+        let next = last_cnt + 1;
+        let (next, lost) = if next < oldest {
+            (oldest, oldest - next)
+        } else {
+            (next, 0)
+        };
+        Some((next, slot.queue[(next - oldest) as usize].clone(), lost))
+    }
+
+    fn wait_for_update(&self, last_cnt: u32) {
+        if let Some(seqlock) = &self.seqlock {
+            while self.cnt() == last_cnt && !self.is_closed() {
+                seqlock.get().wait(last_cnt, Some(CLOSE_SLICE));
+            }
+            return;
+        }
+
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        let guard = self.lock.lock().unwrap();
+        drop(
+            self.condvar
+                .wait_while(guard, |_| self.cnt() == last_cnt && !self.is_closed())
+                .unwrap(),
+        );
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    fn wait_for_update_timeout(&self, last_cnt: u32, timeout: Duration) {
+        if let Some(seqlock) = &self.seqlock {
+            seqlock.get().wait(last_cnt, Some(timeout));
+            return;
+        }
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        let guard = self.lock.lock().unwrap();
+        drop(
+            self.condvar
+                .wait_timeout_while(guard, timeout, |_| {
+                    self.cnt() == last_cnt && !self.is_closed()
+                })
+                .unwrap(),
+        );
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl<T> Channel<T>
+where
+    T: Copy,
+{
+    /// create a channel backed by a seqlock, send never takes a lock and readers never block it.
+    pub fn new_lock_free() -> (Sender<T>, Receiver<T>) {
+        Self::create(0, Some(SeqLockSlot::Local(SeqLock::new())))
+    }
+}
+
+thread_local! {
+    static THREAD_MARK: u8 = const { 0 };
+}
+
+// an unique non-zero id of the current thread
+fn thread_mark() -> usize {
+    THREAD_MARK.with(|x| x as *const u8 as usize)
+}
+
+type CallbackFn<T> = Box<dyn FnMut(&T) + Send>;
+
+struct Callback<T> {
+    func: Mutex<CallbackFn<T>>,
+    pending: Mutex<VecDeque<T>>, // messages waiting for the thread running func
+    running_on: AtomicUsize,     // thread_mark of the thread running func, 0 if idle
+}
+
+impl<T> Callback<T> {
+    fn new<F>(func: F) -> Self
+    where
+        F: FnMut(&T) + Send + 'static,
+    {
+        Self {
+            func: Mutex::new(Box::new(func)),
+            pending: Mutex::new(VecDeque::new()),
+            running_on: AtomicUsize::new(0),
+        }
+    }
+
+    fn call(&self, msg: &T)
+    where
+        T: Clone,
+    {
+        let mark = thread_mark();
+        if self.running_on.load(Ordering::Acquire) == mark {
+            // the callback sent to its own channel, calling it again would deadlock
+            return;
+        }
+        self.pending.lock().unwrap().push_back(msg.clone());
+        // never wait for a busy callback, its thread may be in a nested write waiting for
+        // a callback this thread runs. the thread running it passes on the message instead
+        while let Ok(mut func) = self.func.try_lock() {
+            self.running_on.store(mark, Ordering::Release);
+            loop {
+                let msg = self.pending.lock().unwrap().pop_front();
+                match msg {
+                    Some(msg) => func(&msg),
+                    None => break,
+                }
+            }
+            self.running_on.store(0, Ordering::Release);
+            drop(func);
+            // pushed after the last pop, by a thread which failed to lock func
+            if self.pending.lock().unwrap().is_empty() {
+                break;
+            }
+        }
+    }
+}
+
+// wrap `callback` to run on the work queue thread, see register_deferred_callback
+pub(crate) fn deferred<T, F>(callback: F) -> impl FnMut(&T) + Send + 'static
+where
+    T: Clone + Send + 'static,
+    F: FnMut(&T) + Send + 'static,
+{
+    let deferred = Arc::new(Deferred {
+        callback: Mutex::new(callback),
+        latest: Mutex::new(None),
+    });
+    move |msg: &T| {
+        // a job is queued only if none is waiting, the waiting one takes the latest message
+        if deferred
+            .latest
+            .lock()
+            .unwrap()
+            .replace(msg.clone())
+            .is_none()
+        {
+            // the job does nothing once the callback is unregistered
+            let deferred = Arc::downgrade(&deferred);
+            WORK_QUEUE.add(move || {
+                if let Some(deferred) = deferred.upgrade() {
+                    deferred.run();
+                }
+            });
+        }
+    }
+}
+
+struct Deferred<T, F> {
+    callback: Mutex<F>,
+    latest: Mutex<Option<T>>, // the message waiting for the work queue
+}
+
+impl<T, F> Deferred<T, F>
+where
+    F: FnMut(&T),
+{
+    fn run(&self) {
+        let mut callback = self.callback.lock().unwrap();
+        // take it under the callback lock, so messages are never passed out of order
+        let msg = self.latest.lock().unwrap().take();
+        if let Some(msg) = msg {
+            (callback)(&msg);
+        }
+    }
+}
+
+trait Notify: Send + Sync {
+    fn notify(&self);
+}
+
+// type erased receiver, so receivers of different message types could live in one set
+trait Selectable: Send {
+    fn updated(&self) -> bool;
+    fn closed(&self) -> bool;
+    fn attach(&self, notifier: Arc<dyn Notify>);
+    fn detach(&self, notifier: &Arc<dyn Notify>);
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T> Selectable for Receiver<T>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    fn updated(&self) -> bool {
+        self.has_unread()
+    }
+
+    fn closed(&self) -> bool {
+        self.is_closed()
+    }
+
+    fn attach(&self, notifier: Arc<dyn Notify>) {
+        self.parent.register_notifier(notifier);
+    }
+
+    fn detach(&self, notifier: &Arc<dyn Notify>) {
+        self.parent.unregister_notifier(notifier);
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+struct EventFd {
+    fd: OwnedFd,
+}
+
+impl EventFd {
+    fn new() -> Self {
+        let fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        assert!(fd >= 0, "failed to create eventfd");
+        Self {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        }
+    }
+
+    fn clear(&self) {
+        let mut val: u64 = 0;
+        unsafe {
+            libc::read(
+                self.fd.as_raw_fd(),
+                &mut val as *mut u64 as *mut libc::c_void,
+                std::mem::size_of::<u64>(),
+            );
+        }
+    }
+}
+
+impl Notify for EventFd {
+    fn notify(&self) {
+        let val: u64 = 1;
+        unsafe {
+            libc::write(
+                self.fd.as_raw_fd(),
+                &val as *const u64 as *const libc::c_void,
+                std::mem::size_of::<u64>(),
+            );
+        }
+    }
+}
+
+// wakes the task waiting in Recv or RecvStream
+struct WakerNotifier {
+    waker: Mutex<Option<Waker>>,
+}
+
+impl Notify for WakerNotifier {
+    fn notify(&self) {
+        if let Some(waker) = self.waker.lock().unwrap().take() {
+            waker.wake();
+        }
+    }
+}
+
+fn detach_waker<T>(rx: &Receiver<T>, notifier: &Option<Arc<WakerNotifier>>) {
+    if let Some(notifier) = notifier {
+        let notifier: Arc<dyn Notify> = notifier.clone();
+        rx.parent.unregister_notifier(&notifier);
+    }
+}
+
+/// future returned by `Receiver::recv`.
+pub struct Recv<'a, T> {
+    rx: &'a mut Receiver<T>,
+    notifier: Option<Arc<WakerNotifier>>, // registered on the first poll
+    throttle: Option<Sleep>,              // wakes the task when set_interval allows a read
+}
+
+impl<T> Future for Recv<'_, T>
+where
+    T: Clone,
+{
+    type Output = Result<T, ReadError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        this.rx
+            .poll_recv(&mut this.notifier, &mut this.throttle, cx)
+    }
+}
+
+impl<T> Drop for Recv<'_, T> {
+    fn drop(&mut self) {
+        detach_waker(self.rx, &self.notifier);
+    }
+}
+
+/// stream returned by `Receiver::into_stream`.
+pub struct RecvStream<T> {
+    rx: Receiver<T>,
+    notifier: Option<Arc<WakerNotifier>>,
+    throttle: Option<Sleep>,
+}
+
+impl<T> Stream for RecvStream<T>
+where
+    T: Clone,
+{
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let this = self.get_mut();
+        this.rx
+            .poll_recv(&mut this.notifier, &mut this.throttle, cx)
+            .map(|x| x.ok())
+    }
+}
+
+impl<T> Drop for RecvStream<T> {
+    fn drop(&mut self) {
+        detach_waker(&self.rx, &self.notifier);
+    }
+}
+
+struct SetNotifier {
+    fired: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl Notify for SetNotifier {
+    fn notify(&self) {
+        *self.fired.lock().unwrap() = true;
+        self.condvar.notify_all();
+    }
+}
+
+/// a set of receivers with different message types, `wait` blocks until any of them has
+/// new data and returns its index.
+/// ```ignore
+/// let mut set = ReceiverSet::new();
+/// let gyro = set.add(gyro_rx);
+/// let rc = set.add(rc_rx);
+/// while let Ok(fired) = set.wait() {
+///     if fired == gyro {
+///         let data = set.get_mut::<GyroData>(gyro).unwrap().try_read();
+///     }
+/// }
+/// ```
+pub struct ReceiverSet {
+    receivers: Vec<Box<dyn Selectable>>,
+    notifier: Arc<SetNotifier>,
+    next: usize, // the index to start checking from, so one busy receiver could not starve others
+}
+
+impl ReceiverSet {
+    pub fn new() -> Self {
+        Self {
+            receivers: Vec::new(),
+            notifier: Arc::new(SetNotifier {
+                fired: Mutex::new(false),
+                condvar: Condvar::new(),
+            }),
+            next: 0,
+        }
+    }
+
+    /// move a receiver into the set and return its index.
+    pub fn add<T>(&mut self, rx: Receiver<T>) -> usize
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        rx.attach(self.notifier.clone());
+        self.receivers.push(Box::new(rx));
+        self.receivers.len() - 1
+    }
+
+    /// get the receiver at `index`, return None if the index or the message type is wrong.
+    pub fn get_mut<T>(&mut self, index: usize) -> Option<&mut Receiver<T>>
+    where
+        T: 'static,
+    {
+        self.receivers
+            .get_mut(index)?
+            .as_any_mut()
+            .downcast_mut::<Receiver<T>>()
+    }
+
+    pub fn len(&self) -> usize {
+        self.receivers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.receivers.is_empty()
+    }
+
+    /// block until any receiver has unread data, return its index.
+    /// return `ReadError::Closed` once every receiver is closed and fully read.
+    pub fn wait(&mut self) -> Result<usize, ReadError> {
+        let notifier = self.notifier.clone();
+        let mut fired = notifier.fired.lock().unwrap();
+        loop {
+            *fired = false;
+            if let Some(index) = self.find_updated() {
+                return Ok(index);
+            }
+            if self.all_closed() {
+                return Err(ReadError::Closed);
+            }
+            fired = notifier.condvar.wait_while(fired, |x| !*x).unwrap();
+        }
+    }
+
+    /// like `wait`, but give up after `timeout` escaped on the hrt clock.
+    pub fn wait_timeout(&mut self, timeout: Duration) -> Result<usize, ReadError> {
+        self.wait_until(get_time_now() + Timespec::from(timeout))
+    }
+
+    /// like `wait`, but give up when the hrt clock reaches `deadline`.
+    pub fn wait_until(&mut self, deadline: Timespec) -> Result<usize, ReadError> {
+        let notifier = self.notifier.clone();
+        let mut fired = notifier.fired.lock().unwrap();
+        loop {
+            *fired = false;
+            if let Some(index) = self.find_updated() {
+                return Ok(index);
+            }
+            if self.all_closed() {
+                return Err(ReadError::Closed);
+            }
+            let now = get_time_now();
+            if now >= deadline {
+                return Err(ReadError::Timeout);
+            }
+            let remain = Duration::from_nanos((deadline - now).to_nano() as u64);
+            fired = notifier
+                .condvar
+                .wait_timeout_while(fired, wait_slice(remain), |x| !*x)
+                .unwrap()
+                .0;
+        }
+    }
+
+    fn find_updated(&mut self) -> Option<usize> {
+        let len = self.receivers.len();
+        let index = (0..len)
+            .map(|i| (self.next + i) % len)
+            .find(|i| self.receivers[*i].updated())?;
+        self.next = (index + 1) % len;
+        Some(index)
+    }
+
+    fn all_closed(&self) -> bool {
+        self.receivers.iter().all(|rx| rx.closed())
+    }
+}
+
+impl Default for ReceiverSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for ReceiverSet {
+    fn drop(&mut self) {
+        let notifier: Arc<dyn Notify> = self.notifier.clone();
+        for rx in self.receivers.iter() {
+            rx.detach(&notifier);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[derive(Debug, Default, Clone, Copy)]
+    struct TestStruct {
+        x: u32,
+        y: u32,
+        z: u32,
+    }
+
+    #[test]
+    fn test_basic_rxtx() {
+        let (tx, mut rx) = Channel::<TestStruct>::new();
+
+        tx.send(TestStruct::default());
+        rx.read().unwrap();
+        assert_eq!(rx.last_cnt, 1);
+
+        let try_result = rx.try_read();
+        assert!(try_result.is_none());
+    }
+
+    #[test]
+    fn test_channel_drop() {
+        let (tx, rx) = Channel::<TestStruct>::new();
+        {
+            let _rx2 = rx.clone();
+            assert_eq!(rx.parent.receiver_cnt.load(Ordering::Relaxed), 2);
+        }
+        assert_eq!(rx.parent.receiver_cnt.load(Ordering::Relaxed), 1);
+        assert!(!rx.is_closed());
+
+        drop(tx);
+        assert_eq!(rx.parent.sender_cnt.load(Ordering::Relaxed), 0);
+        assert!(rx.is_closed());
+        // the receiver is the last owner
+        assert_eq!(Arc::strong_count(&rx.parent), 1);
+
+        // closed with a sender alive
+        let (tx, mut rx) = Channel::<u32>::new();
+        tx.send(1);
+        std::thread::spawn({
+            let tx = tx.clone();
+            move || {
+                std::thread::sleep(Duration::from_millis(10));
+                tx.close();
+            }
+        });
+        assert_eq!(rx.read().unwrap(), 1);
+        assert_eq!(rx.read().unwrap_err(), ReadError::Closed);
+        assert!(rx.is_closed());
+        // messages sent after closing are dropped
+        tx.send(2);
+        assert!(rx.try_read().is_none());
+    }
+
+    #[test]
+    fn test_no_data() {
+        let (tx, mut rx) = Channel::<String>::new();
+        assert!(!rx.has_data());
+        assert!(rx.try_read().is_none());
+        tx.send("hello".to_string());
+        assert!(rx.has_data());
+        assert_eq!(rx.try_read().unwrap(), "hello");
+        // still has data after reading it
+        assert!(rx.has_data());
+
+        let (_, rx) = Channel::<u32>::new_lock_free();
+        assert!(!rx.has_data());
+
+        let (tx, mut rx) = Channel::with_initial(vec![1, 2]);
+        assert!(rx.has_data());
+        assert_eq!(rx.try_read().unwrap(), vec![1, 2]);
+        assert!(rx.try_read().is_none());
+        assert_eq!(tx.stats().publish_cnt, 0);
+        tx.send(vec![3]);
+        assert_eq!(rx.try_read().unwrap(), vec![3]);
+    }
+
+    #[test]
+    fn test_read_closed() {
+        let (tx, mut rx) = Channel::<TestStruct>::new();
+        tx.send(TestStruct { x: 1, y: 2, z: 3 });
+        drop(tx);
+        // the message sent before closing is still readable
+        assert_eq!(rx.read().unwrap().x, 1);
+        assert_eq!(rx.read().unwrap_err(), ReadError::Closed);
+        assert_eq!(
+            rx.read_timeout(Duration::from_secs(1)).unwrap_err(),
+            ReadError::Closed
+        );
+
+        // a blocked read wakes up when the last sender is dropped
+        let (tx, mut rx) = Channel::<TestStruct>::new_lock_free();
+        let tx2 = tx.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            drop(tx);
+            drop(tx2);
+        });
+        assert_eq!(rx.read().unwrap_err(), ReadError::Closed);
+
+        let mut rx = Receiver::<TestStruct>::default();
+        assert!(rx.is_closed());
+        assert_eq!(rx.read().unwrap_err(), ReadError::Closed);
+        Sender::<TestStruct>::default().send(TestStruct::default());
+    }
+
+    #[test]
+    fn test_block_read() {
+        let (tx, mut rx) = Channel::<TestStruct>::new();
+        let start_time = std::time::SystemTime::now();
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_secs(5));
+            tx.send(TestStruct::default());
+        });
+        rx.read().unwrap();
+
+        let escape = start_time.elapsed().unwrap();
+
+        assert!(escape.as_secs() >= 5);
+    }
+
+    #[test]
+    fn test_channel_callback() {
+        fn test_func(msg: &TestStruct) {
             std::thread::sleep(std::time::Duration::from_secs(5));
         }
         let (tx, mut rx) = Channel::<TestStruct>::new();
@@ -307,4 +1584,447 @@ mod tests {
         tx.send(TestStruct::default());
         assert!(start_time.elapsed().unwrap().as_secs() <= 1);
     }
+
+    #[test]
+    fn test_read_timeout() {
+        let (tx, mut rx) = Channel::<TestStruct>::new();
+        let start_time = std::time::SystemTime::now();
+        assert_eq!(
+            rx.read_timeout(Duration::from_millis(50)).unwrap_err(),
+            ReadError::Timeout
+        );
+        assert!(start_time.elapsed().unwrap() >= Duration::from_millis(50));
+
+        let tx2 = tx.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            tx2.send(TestStruct { x: 1, y: 2, z: 3 });
+        });
+        let deadline = get_time_now() + Timespec::from_secs(1);
+        assert_eq!(rx.read_until(deadline).unwrap().x, 1);
+        // a huge timeout saturates instead of timing out at once
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            tx.send(TestStruct { x: 4, y: 5, z: 6 });
+        });
+        assert_eq!(rx.read_timeout(Duration::MAX).unwrap().x, 4);
+    }
+
+    #[test]
+    fn test_lock_free_rxtx() {
+        let (tx, mut rx) = Channel::<TestStruct>::new_lock_free();
+        assert!(rx.try_read().is_none());
+
+        tx.send(TestStruct { x: 1, y: 2, z: 3 });
+        assert_eq!(rx.read().unwrap().y, 2);
+        assert!(rx.try_read().is_none());
+
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            tx.send(TestStruct { x: 4, y: 5, z: 6 });
+        });
+        assert_eq!(rx.read().unwrap().z, 6);
+    }
+
+    #[test]
+    fn test_receiver_set() {
+        let (tx_a, rx_a) = Channel::<TestStruct>::new();
+        let (tx_b, rx_b) = Channel::<u32>::new_lock_free();
+        let mut set = ReceiverSet::new();
+        let a = set.add(rx_a);
+        let b = set.add(rx_b);
+        assert!(set.get_mut::<u32>(a).is_none());
+
+        assert_eq!(
+            set.wait_timeout(Duration::from_millis(10)).unwrap_err(),
+            ReadError::Timeout
+        );
+
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            tx_b.send(7);
+        });
+        assert_eq!(set.wait(), Ok(b));
+        assert_eq!(set.get_mut::<u32>(b).unwrap().try_read(), Some(7));
+
+        tx_a.send(TestStruct::default());
+        assert_eq!(set.wait(), Ok(a));
+        assert!(set.get_mut::<TestStruct>(a).unwrap().try_read().is_some());
+        assert!(set.wait_timeout(Duration::from_millis(10)).is_err());
+
+        // b is closed already, wait returns when the last one closes
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            drop(tx_a);
+        });
+        assert_eq!(set.wait(), Err(ReadError::Closed));
+        assert_eq!(
+            set.wait_timeout(Duration::from_millis(10)),
+            Err(ReadError::Closed)
+        );
+    }
+
+    #[test]
+    fn test_deferred_callback() {
+        let (tx, rx) = Channel::<TestStruct>::new();
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+        rx.register_deferred_callback("test_deferred_cb", move |msg: &TestStruct| {
+            done_tx.send(msg.x).unwrap();
+            std::thread::sleep(Duration::from_millis(200));
+        });
+
+        let start_time = std::time::SystemTime::now();
+        tx.send(TestStruct { x: 1, y: 0, z: 0 });
+        assert_eq!(done_rx.recv().unwrap(), 1);
+        // the callback is still busy with 1, 3 replaces 2 in the waiting job
+        tx.send(TestStruct { x: 2, y: 0, z: 0 });
+        tx.send(TestStruct { x: 3, y: 0, z: 0 });
+        assert!(start_time.elapsed().unwrap() < Duration::from_millis(100));
+        assert_eq!(done_rx.recv().unwrap(), 3);
+
+        // the job waiting when the callback is unregistered is skipped,
+        // done_tx is dropped with the callback then
+        tx.send(TestStruct { x: 4, y: 0, z: 0 });
+        rx.unregister_callback("test_deferred_cb");
+        assert!(done_rx.recv().is_err());
+    }
+
+    #[test]
+    fn test_callback_send_to_same_channel() {
+        let (tx, mut rx) = Channel::<u32>::new();
+        let inner_tx = tx.clone();
+        rx.register_callback("echo", move |msg: &u32| {
+            if *msg < 10 {
+                inner_tx.send(msg + 10);
+            }
+        });
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+        let inner_tx = tx.clone();
+        rx.register_deferred_callback("deferred_echo", move |msg: &u32| {
+            if *msg < 100 {
+                inner_tx.send(100);
+            }
+            done_tx.send(*msg).unwrap();
+        });
+
+        tx.send(1);
+        assert_eq!(rx.try_read(), Some(1));
+        // "echo" is not called again with the 11 it sent itself, and the deferred job may
+        // merge 1 and 11
+        let got: Vec<u32> = done_rx.iter().take_while(|x| *x != 100).collect();
+        assert!(!got.is_empty() && got.iter().all(|x| *x == 1 || *x == 11));
+        rx.unregister_callback("echo");
+        rx.unregister_callback("deferred_echo");
+    }
+
+    #[test]
+    fn test_callbacks_send_to_same_channel() {
+        // each callback waits for the other one in its nested send, on two threads
+        for _ in 0..2000 {
+            let (tx, rx) = Channel::<u32>::new();
+            let calls = Arc::new([AtomicUsize::new(0), AtomicUsize::new(0)]);
+            for (i, name) in ["a", "b"].into_iter().enumerate() {
+                let inner_tx = tx.clone();
+                let calls = calls.clone();
+                rx.register_callback(name, move |msg: &u32| {
+                    calls[i].fetch_add(1, Ordering::Relaxed);
+                    if *msg < 10 {
+                        inner_tx.send(msg + 10);
+                    }
+                });
+            }
+
+            let barrier = Arc::new(std::sync::Barrier::new(2));
+            let senders: Vec<_> = (0..2)
+                .map(|x| {
+                    let tx = tx.clone();
+                    let barrier = barrier.clone();
+                    std::thread::spawn(move || {
+                        barrier.wait();
+                        tx.send(x);
+                    })
+                })
+                .collect();
+            for sender in senders {
+                sender.join().unwrap();
+            }
+            // both get the messages sent by the threads, and some of the ones the other
+            // callback sent, a nested send is skipped for a callback running on the same thread
+            for x in calls.iter() {
+                assert!((2..=4).contains(&x.load(Ordering::Relaxed)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_channel_stats() {
+        let (tx, rx) = Channel::<u32>::new_lock_free();
+        let _rx2 = rx.clone();
+        let stats = rx.stats();
+        assert_eq!(stats.publish_cnt, 0);
+        assert!(stats.last_publish.is_none());
+        assert_eq!(stats.sender_cnt, 1);
+        assert_eq!(stats.receiver_cnt, 2);
+
+        for i in 0..5 {
+            tx.send(i);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let stats = tx.stats();
+        assert_eq!(stats.publish_cnt, 5);
+        assert!(stats.last_publish.unwrap() <= get_time_now());
+        assert!(stats.min_rate <= stats.avg_rate && stats.avg_rate <= stats.max_rate);
+        assert!(stats.max_rate <= 100.0);
+        assert!(stats.min_rate > 10.0);
+
+        // publishes at the same time have no measurable rate
+        let (tx, _rx) = Channel::<u32>::new();
+        let now = get_time_now();
+        tx.parent.publish_stats.record(now);
+        tx.parent.publish_stats.record(now);
+        let stats = tx.stats();
+        assert_eq!(stats.publish_cnt, 2);
+        assert_eq!(
+            (stats.avg_rate, stats.min_rate, stats.max_rate),
+            (0.0, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn test_message_timestamp() {
+        let (tx, mut rx) = Channel::<u32>::new();
+        let (lock_free_tx, mut lock_free_rx) = Channel::<u32>::new_lock_free();
+        assert!(rx.age().is_none());
+        assert!(lock_free_rx.age().is_none());
+
+        let before = get_time_now();
+        tx.send(1);
+        lock_free_tx.send(1);
+        let (data, timestamp) = rx.read_with_timestamp().unwrap();
+        assert_eq!(data, 1);
+        assert!(timestamp >= before && timestamp <= get_time_now());
+        assert!(lock_free_rx.read_with_timestamp().unwrap().1 >= before);
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(rx.age().unwrap() >= Duration::from_millis(20));
+        assert!(lock_free_rx.age().unwrap() >= Duration::from_millis(20));
+
+        tx.send(2);
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(rx.try_read_fresh(Duration::from_millis(10)).is_none());
+        // the stale message is consumed
+        assert!(rx.try_read().is_none());
+        tx.send(3);
+        assert_eq!(rx.try_read_fresh(Duration::from_millis(10)), Some(3));
+    }
+
+    #[test]
+    fn test_receiver_queue() {
+        let (tx, mut rx) = Channel::<u32>::new();
+        tx.send(0);
+        let mut logger = rx.with_queue(4);
+        // only messages after with_queue are queued
+        assert!(logger.try_read().is_none());
+
+        for i in 1..=3 {
+            tx.send(i);
+        }
+        assert_eq!(rx.try_read(), Some(3));
+        assert_eq!(logger.try_read(), Some(1));
+
+        // overflow the queue, 2 to 5 are lost
+        for i in 4..=9 {
+            tx.send(i);
+        }
+        let data: Vec<u32> = std::iter::from_fn(|| logger.try_read()).collect();
+        assert_eq!(data, vec![6, 7, 8, 9]);
+        assert_eq!(logger.lost_cnt(), 4);
+
+        let mut logger2 = logger.clone();
+        let (lock_free_tx, lock_free_rx) = Channel::<u32>::new_lock_free();
+        let mut lock_free_logger = lock_free_rx.with_queue(8);
+        std::thread::spawn(move || {
+            for i in 10..13 {
+                tx.send(i);
+                lock_free_tx.send(i);
+            }
+        });
+        for i in 10..13 {
+            assert_eq!(logger.read().unwrap(), i);
+            assert_eq!(logger2.read().unwrap(), i);
+            assert_eq!(lock_free_logger.read().unwrap(), i);
+        }
+        assert_eq!(logger.read().unwrap_err(), ReadError::Closed);
+        assert_eq!(lock_free_logger.lost_cnt(), 0);
+    }
+
+    #[test]
+    fn test_throttled_callback() {
+        let (tx, rx) = Channel::<u32>::new();
+        let throttled = Arc::new(Mutex::new(Vec::new()));
+        let filtered = Arc::new(Mutex::new(Vec::new()));
+        {
+            let throttled = throttled.clone();
+            rx.register_callback_with_interval("throttled", Duration::from_millis(50), move |x| {
+                throttled.lock().unwrap().push(*x)
+            });
+            let filtered = filtered.clone();
+            rx.register_callback_with_filter(
+                "filtered",
+                |x| x % 2 == 0,
+                move |x| filtered.lock().unwrap().push(*x),
+            );
+        }
+        for i in 0..4 {
+            tx.send(i);
+        }
+        std::thread::sleep(Duration::from_millis(60));
+        tx.send(4);
+        assert_eq!(*throttled.lock().unwrap(), vec![0, 4]);
+        assert_eq!(*filtered.lock().unwrap(), vec![0, 2, 4]);
+    }
+
+    #[test]
+    fn test_read_interval() {
+        let (tx, mut rx) = Channel::<u32>::new();
+        rx.set_interval(Duration::from_millis(50));
+        tx.send(1);
+        assert_eq!(rx.try_read(), Some(1));
+        tx.send(2);
+        assert!(rx.try_read().is_none());
+
+        let start_time = get_time_now();
+        assert_eq!(rx.read().unwrap(), 2);
+        assert!((get_time_now() - start_time).to_nano() >= 40_000_000);
+
+        rx.set_interval(Duration::ZERO);
+        tx.send(3);
+        assert_eq!(rx.try_read(), Some(3));
+    }
+
+    #[test]
+    fn test_with_latest() {
+        struct NoCopy(Vec<u32>);
+        impl Clone for NoCopy {
+            fn clone(&self) -> Self {
+                panic!("the message should not be cloned");
+            }
+        }
+
+        let (tx, rx) = Channel::<NoCopy>::new();
+        assert!(rx.with_latest(|x| x.0.len()).is_none());
+        tx.send(NoCopy(vec![1, 2, 3]));
+        tx.send(NoCopy(vec![4; 100]));
+        assert_eq!(rx.with_latest(|x| x.0.len()), Some(100));
+
+        let (tx, rx) = Channel::<u32>::with_queue(2);
+        tx.send(1);
+        tx.send(2);
+        assert_eq!(rx.with_latest(|x| *x), Some(2));
+        let (tx, rx) = Channel::<u32>::new_lock_free();
+        tx.send(3);
+        assert_eq!(rx.with_latest(|x| *x), Some(3));
+
+        // a large message without Clone is shared by Arc
+        struct PointCloud {
+            points: Vec<[f32; 3]>,
+        }
+        let (tx, mut rx) = Channel::<Arc<PointCloud>>::new();
+        let mut rx2 = rx.clone();
+        tx.send_shared(PointCloud {
+            points: vec![[0.0; 3]; 1000],
+        });
+        let a = rx.try_read().unwrap();
+        let b = rx2.try_read().unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+        assert_eq!(a.points.len(), 1000);
+    }
+
+    #[test]
+    fn test_receiver_poll() {
+        use polling::{Event, Events, Poller};
+
+        let (tx, mut rx) = Channel::<u32>::with_queue(4);
+        let poller = Poller::new().unwrap();
+        let mut events = Events::new();
+        unsafe { poller.add(&rx, Event::readable(7)).unwrap() };
+        poller
+            .wait(&mut events, Some(Duration::from_millis(10)))
+            .unwrap();
+        assert!(events.is_empty());
+
+        tx.send(1);
+        tx.send(2);
+        poller
+            .wait(&mut events, Some(Duration::from_secs(1)))
+            .unwrap();
+        assert_eq!(events.iter().next().unwrap().key, 7);
+
+        // still readable, one message left in the queue
+        assert_eq!(rx.try_read(), Some(1));
+        events.clear();
+        poller.modify(&rx, Event::readable(7)).unwrap();
+        poller
+            .wait(&mut events, Some(Duration::from_secs(1)))
+            .unwrap();
+        assert_eq!(events.len(), 1);
+
+        assert_eq!(rx.try_read(), Some(2));
+        events.clear();
+        poller.modify(&rx, Event::readable(7)).unwrap();
+        poller
+            .wait(&mut events, Some(Duration::from_millis(10)))
+            .unwrap();
+        assert!(events.is_empty());
+        poller.delete(&rx).unwrap();
+    }
+
+    #[test]
+    fn test_queue_rxtx() {
+        let (tx, mut rx) = Channel::<u32>::with_queue(4);
+
+        tx.send(1);
+        tx.send(2);
+        tx.send(3);
+        assert_eq!(rx.read().unwrap(), 1);
+        assert_eq!(rx.try_read(), Some(2));
+        assert_eq!(rx.try_read(), Some(3));
+        assert!(rx.try_read().is_none());
+        assert_eq!(rx.lost_cnt(), 0);
+    }
+
+    #[test]
+    fn test_queue_overflow() {
+        let (tx, mut rx) = Channel::<u32>::with_queue(2);
+        let mut rx2 = rx.clone();
+
+        for i in 1..=5 {
+            tx.send(i);
+        }
+        assert_eq!(rx.try_read(), Some(4));
+        assert_eq!(rx.lost_cnt(), 3);
+        assert_eq!(rx.try_read(), Some(5));
+        assert!(rx.try_read().is_none());
+
+        // each receiver keeps its own position in the queue
+        assert_eq!(rx2.read().unwrap(), 4);
+        assert_eq!(rx2.lost_cnt(), 3);
+    }
+
+    #[test]
+    fn test_queue_reorder() {
+        let (tx, rx) = Channel::<u32>::new_lock_free();
+        let mut rx = rx.with_queue(4);
+        let stamped = |data| Stamped { timestamp: 0, data };
+
+        // two writers pushed their messages to the queue out of order
+        tx.parent.push_subscribers(2, stamped(2));
+        tx.parent.push_subscribers(1, stamped(1));
+        tx.parent.push_subscribers(3, stamped(3));
+        assert_eq!(rx.try_read(), Some(2));
+        assert_eq!(rx.try_read(), Some(1));
+        assert_eq!(rx.try_read(), Some(3));
+        // 1 is counted lost when 2 is read, the late 1 never counts again
+        assert_eq!(rx.lost_cnt(), 1);
+    }
 }
@@ -0,0 +1,407 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    task::{Context, Poll, Wake, Waker},
+    time::Duration,
+};
+
+use libc::c_long;
+
+use crate::{
+    hrt::{get_time_now, Timespec},
+    pthread::nanosleep,
+    pthread_scheduler::SchedulePthread,
+};
+
+// when lock step drives the hrt clock, real time tells nothing about the next deadline,
+// so never sleep longer than this before checking the timers again.
+const TIMER_SLICE: Duration = Duration::from_millis(1);
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+thread_local! {
+    // the executor running on this thread, used by Sleep to register its timer
+    static CURRENT: RefCell<Option<Arc<Executor>>> = const { RefCell::new(None) };
+}
+
+/*
+    a small single thread executor, so async tasks(like waiting on `Receiver::recv`)
+    could run on a SchedulePthread without an external runtime.
+    timers(`sleep`, `sleep_until`) follow the hrt clock.
+*/
+pub struct Executor {
+    ready: Mutex<VecDeque<Arc<Task>>>,
+    condvar: Condvar,
+    timers: Mutex<HashMap<u64, (Timespec, Waker)>>, // by the id of the Sleep
+    next_timer: AtomicU64,
+    task_cnt: AtomicUsize,
+}
+
+struct Task {
+    future: Mutex<Option<BoxFuture>>, // None once finished
+    executor: Arc<Executor>,
+    queued: AtomicBool,
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        if !self.queued.swap(true, Ordering::AcqRel) {
+            let executor = self.executor.clone();
+            executor.ready.lock().unwrap().push_back(self);
+            executor.condvar.notify_one();
+        }
+    }
+}
+
+impl Task {
+    fn poll(self: Arc<Self>) {
+        self.queued.store(false, Ordering::Release);
+        let mut future = self.future.lock().unwrap();
+        if let Some(fut) = future.as_mut() {
+            let waker = Waker::from(self.clone());
+            if fut
+                .as_mut()
+                .poll(&mut Context::from_waker(&waker))
+                .is_ready()
+            {
+                *future = None;
+                self.executor.task_cnt.fetch_sub(1, Ordering::AcqRel);
+            }
+        }
+    }
+}
+
+impl Executor {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Executor {
+            ready: Mutex::new(VecDeque::new()),
+            condvar: Condvar::new(),
+            timers: Mutex::new(HashMap::new()),
+            next_timer: AtomicU64::new(0),
+            task_cnt: AtomicUsize::new(0),
+        })
+    }
+
+    /// add a task, it starts running once `run` is called(or right away if it's running).
+    pub fn spawn<F>(self: &Arc<Self>, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.task_cnt.fetch_add(1, Ordering::AcqRel);
+        let task = Arc::new(Task {
+            future: Mutex::new(Some(Box::pin(future))),
+            executor: self.clone(),
+            queued: AtomicBool::new(false),
+        });
+        task.wake();
+    }
+
+    /// run the tasks on the current thread, return when every task is finished.
+    pub fn run(self: &Arc<Self>) {
+        CURRENT.with(|x| *x.borrow_mut() = Some(self.clone()));
+        while self.task_cnt.load(Ordering::Acquire) > 0 {
+            self.fire_timers();
+            let task = {
+                let mut ready = self.ready.lock().unwrap();
+                if ready.is_empty() {
+                    ready = match self.next_timeout() {
+                        Some(timeout) => {
+                            self.condvar
+                                .wait_timeout_while(ready, timeout, |x| x.is_empty())
+                                .unwrap()
+                                .0
+                        }
+                        None => self.condvar.wait_while(ready, |x| x.is_empty()).unwrap(),
+                    };
+                }
+                ready.pop_front()
+            };
+            if let Some(task) = task {
+                task.poll();
+            }
+        }
+        CURRENT.with(|x| *x.borrow_mut() = None);
+    }
+
+    /// run the tasks on a new SchedulePthread, spawn the tasks before starting it,
+    /// the pthread exits when every task is finished.
+    pub fn start(self: &Arc<Self>) -> Arc<SchedulePthread> {
+        let executor = self.clone();
+        SchedulePthread::new_simple(Box::new(move |_| executor.run()))
+    }
+
+    // return the id to update or remove the timer with
+    fn add_timer(&self, deadline: Timespec, waker: Waker) -> u64 {
+        let id = self.next_timer.fetch_add(1, Ordering::AcqRel);
+        self.timers.lock().unwrap().insert(id, (deadline, waker));
+        self.condvar.notify_one();
+        id
+    }
+
+    fn update_timer(&self, id: u64, waker: &Waker) {
+        if let Some((_, old)) = self.timers.lock().unwrap().get_mut(&id) {
+            if !old.will_wake(waker) {
+                *old = waker.clone();
+            }
+        }
+    }
+
+    fn remove_timer(&self, id: u64) {
+        self.timers.lock().unwrap().remove(&id);
+    }
+
+    fn fire_timers(&self) {
+        let now = get_time_now();
+        let mut fired = Vec::new();
+        self.timers.lock().unwrap().retain(|_, (deadline, waker)| {
+            if *deadline <= now {
+                fired.push(waker.clone());
+                false
+            } else {
+                true
+            }
+        });
+        // wake without the timers lock, the task may add a new timer
+        fired.into_iter().for_each(Waker::wake);
+    }
+
+    // how long to wait for the next timer, None if there is no timer
+    fn next_timeout(&self) -> Option<Duration> {
+        let timers = self.timers.lock().unwrap();
+        let deadline = timers
+            .values()
+            .map(|(deadline, _)| deadline.to_nano())
+            .min()?;
+        let remain = Duration::from_nanos((deadline - get_time_now().to_nano()).max(0) as u64);
+        if cfg!(feature = "lock_step_enabled") {
+            Some(remain.min(TIMER_SLICE))
+        } else {
+            Some(remain)
+        }
+    }
+}
+
+/// future returned by `sleep` and `sleep_until`.
+pub struct Sleep {
+    deadline: Timespec,
+    timer: Option<Timer>, // registered on the first pending poll
+}
+
+enum Timer {
+    Executor(Arc<Executor>, u64),
+    Thread(Arc<Mutex<Waker>>), // awaited outside of an rpos executor
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if get_time_now() >= self.deadline {
+            return Poll::Ready(());
+        }
+        match &self.timer {
+            Some(Timer::Executor(executor, id)) => executor.update_timer(*id, cx.waker()),
+            Some(Timer::Thread(waker)) => waker.lock().unwrap().clone_from(cx.waker()),
+            None => {
+                let timer = match CURRENT.with(|x| x.borrow().clone()) {
+                    Some(executor) => {
+                        let id = executor.add_timer(self.deadline, cx.waker().clone());
+                        Timer::Executor(executor, id)
+                    }
+                    None => Timer::Thread(wake_by_thread(self.deadline, cx.waker().clone())),
+                };
+                self.timer = Some(timer);
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(Timer::Executor(executor, id)) = &self.timer {
+            executor.remove_timer(*id);
+        }
+    }
+}
+
+// a thread waits for the deadline on the hrt clock and wakes the latest waker
+fn wake_by_thread(deadline: Timespec, waker: Waker) -> Arc<Mutex<Waker>> {
+    let waker = Arc::new(Mutex::new(waker));
+    let thread_waker = waker.clone();
+    std::thread::spawn(move || {
+        loop {
+            let remain = (deadline - get_time_now()).to_nano();
+            if remain <= 0 {
+                break;
+            }
+            nanosleep(remain.min(999_999_999) as c_long);
+        }
+        thread_waker.lock().unwrap().wake_by_ref();
+    });
+    waker
+}
+
+/// complete after `duration` escaped on the hrt clock.
+/// awaited outside of an rpos executor, a helper thread wakes the task.
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(get_time_now() + Timespec::from(duration))
+}
+
+/// complete when the hrt clock reaches `deadline`.
+pub fn sleep_until(deadline: Timespec) -> Sleep {
+    Sleep {
+        deadline,
+        timer: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::{Channel, ReadError, Receiver};
+    use futures_core::Stream;
+
+    #[test]
+    fn test_executor_recv() {
+        let executor = Executor::new();
+        let (tx, mut rx) = Channel::<u32>::new();
+        let (result_tx, mut result_rx) = Channel::<Vec<u32>>::new();
+
+        executor.spawn(async move {
+            let mut result = Vec::new();
+            while let Ok(data) = rx.recv().await {
+                result.push(data);
+            }
+            result_tx.send(result);
+        });
+        executor.spawn(async move {
+            for i in 0..3 {
+                sleep(Duration::from_millis(10)).await;
+                tx.send(i);
+            }
+        });
+
+        let start_time = get_time_now();
+        executor.start().join();
+        assert!((get_time_now() - start_time).to_nano() >= 30_000_000);
+        assert_eq!(result_rx.try_read().unwrap(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_recv_interval() {
+        let executor = Executor::new();
+        let (tx, mut rx) = Channel::<u32>::new();
+        rx.set_interval(Duration::from_millis(50));
+        tx.send(1);
+
+        executor.spawn(async move {
+            assert_eq!(rx.recv().await, Ok(1));
+            let start_time = get_time_now();
+            // nothing is sent after 2, only the timer of the interval wakes the task
+            tx.send(2);
+            assert_eq!(rx.recv().await, Ok(2));
+            assert!((get_time_now() - start_time).to_nano() >= 40_000_000);
+        });
+        executor.run();
+
+        // the same outside of an rpos executor
+        let (tx, mut rx) = Channel::<u32>::new();
+        rx.set_interval(Duration::from_millis(50));
+        tx.send(1);
+        assert_eq!(rx.try_read(), Some(1));
+        tx.send(2);
+        let flag = Arc::new(WakeFlag::default());
+        let waker = Waker::from(flag.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut recv = std::pin::pin!(rx.recv());
+        assert!(recv.as_mut().poll(&mut cx).is_pending());
+        // a helper thread wakes it when the interval is over
+        let woken = flag.0.lock().unwrap();
+        let (_woken, result) = flag
+            .1
+            .wait_timeout_while(woken, Duration::from_secs(1), |x| !*x)
+            .unwrap();
+        assert!(!result.timed_out());
+        assert_eq!(recv.as_mut().poll(&mut cx), Poll::Ready(Ok(2)));
+    }
+
+    #[derive(Default)]
+    struct WakeFlag(Mutex<bool>, Condvar);
+
+    impl Wake for WakeFlag {
+        fn wake(self: Arc<Self>) {
+            *self.0.lock().unwrap() = true;
+            self.1.notify_all();
+        }
+    }
+
+    #[test]
+    fn test_sleep_timer() {
+        let executor = Executor::new();
+        let timers = executor.clone();
+        executor.spawn(async move {
+            let mut timer = sleep(Duration::from_millis(20));
+            for _ in 0..3 {
+                std::future::poll_fn(|cx| {
+                    assert!(Pin::new(&mut timer).poll(cx).is_pending());
+                    Poll::Ready(())
+                })
+                .await;
+            }
+            // polled three times, registered once
+            assert_eq!(timers.timers.lock().unwrap().len(), 1);
+            timer.await;
+            assert!(timers.timers.lock().unwrap().is_empty());
+
+            // a dropped sleep leaves no timer behind
+            let mut timer = sleep(Duration::from_secs(10));
+            std::future::poll_fn(|cx| {
+                assert!(Pin::new(&mut timer).poll(cx).is_pending());
+                Poll::Ready(())
+            })
+            .await;
+            drop(timer);
+            assert!(timers.timers.lock().unwrap().is_empty());
+        });
+        executor.run();
+    }
+
+    #[test]
+    fn test_executor_stream() {
+        let executor = Executor::new();
+        let (tx, rx) = Channel::<u32>::with_queue(8);
+        let mut stream = rx.into_stream();
+        let result = Arc::new(Mutex::new(Vec::new()));
+
+        let result2 = result.clone();
+        executor.spawn(async move {
+            while let Some(data) =
+                std::future::poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await
+            {
+                result2.lock().unwrap().push(data);
+            }
+        });
+        std::thread::spawn(move || {
+            for i in 0..5 {
+                tx.send(i);
+            }
+        });
+
+        executor.run();
+        assert_eq!(*result.lock().unwrap(), vec![0, 1, 2, 3, 4]);
+
+        let mut rx = Receiver::<u32>::default();
+        let executor = Executor::new();
+        executor.spawn(async move {
+            assert_eq!(rx.recv().await.unwrap_err(), ReadError::Closed);
+        });
+        executor.run();
+    }
+}
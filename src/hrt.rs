@@ -3,6 +3,7 @@ use std::{
     collections::VecDeque,
     ops::{Add, Sub},
     sync::{LazyLock, Mutex},
+    time::Duration,
 };
 
 use libc::c_long;
@@ -40,6 +41,23 @@ impl From<libc::timespec> for Timespec {
     }
 }
 
+// the longest duration whose deadline from now still fits the i64 nanoseconds of to_nano,
+// about 146 years
+const MAX_DURATION_SECS: u64 = i64::MAX as u64 / 1000 / 1000 / 1000 / 2;
+
+impl From<Duration> for Timespec {
+    // saturates, so a timeout like Duration::MAX waits forever instead of wrapping around
+    fn from(value: Duration) -> Self {
+        if value.as_secs() >= MAX_DURATION_SECS {
+            return Self::from_secs(MAX_DURATION_SECS as i64);
+        }
+        Self {
+            sec: value.as_secs() as c_long,
+            nsec: value.subsec_nanos() as c_long,
+        }
+    }
+}
+
 impl Sub for Timespec {
     type Output = Self;
     fn sub(self, rhs: Self) -> Self {
@@ -91,6 +109,13 @@ impl Timespec {
     pub fn from_secs(sec: i64) -> Self {
         Self { sec:sec as c_long, nsec: 0 }
     }
+
+    pub fn from_nano(nano: i64) -> Self {
+        Self {
+            sec: (nano / 1000 / 1000 / 1000) as c_long,
+            nsec: (nano % (1000 * 1000 * 1000)) as c_long,
+        }
+    }
 }
 
 pub fn get_time_now() -> Timespec {
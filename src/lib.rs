@@ -5,7 +5,13 @@ pub mod lock_step;
 pub mod module;
 pub mod pthread_scheduler;
 pub mod channel;
+mod seqlock;
 pub mod server_client;
+pub mod shm;
+pub mod work_queue;
+pub mod executor;
+mod listener;
+pub mod bridge;
 
 pub use ctor;
 pub use libc;
@@ -0,0 +1,224 @@
+use std::{io::Write, time::Duration};
+
+use crate::{
+    channel::ReadError, module::Module, msg::get_formatted_rx_of_message_instance,
+    server_client::get_output,
+};
+
+const USAGE: &str = "usage: listener <topic> [-n count] [-r rate]";
+
+// stop listening if the topic is not updated in this time, after the interval of -r
+const LISTEN_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, PartialEq)]
+struct ListenerArgs {
+    topic: String,
+    count: u32,                 // 0 means listening until the topic times out or closes
+    interval: Option<Duration>, // min time between prints, from the max prints per second
+}
+
+fn parse_args(args: &[&str]) -> Result<ListenerArgs, String> {
+    let mut topic = None;
+    let mut count = 1;
+    let mut interval = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match *arg {
+            "-n" | "-r" => {
+                let value = iter
+                    .next()
+                    .ok_or_else(|| format!("{} needs a value", arg))?;
+                if *arg == "-n" {
+                    count = value
+                        .parse()
+                        .map_err(|_| format!("invalid count: {}", value))?;
+                } else {
+                    // a tiny rate overflows the interval
+                    interval = match value.parse::<f64>() {
+                        Ok(x) if x > 0.0 => Duration::try_from_secs_f64(1.0 / x).ok(),
+                        _ => None,
+                    };
+                    if interval.is_none() {
+                        return Err(format!("invalid rate: {}", value));
+                    }
+                }
+            }
+            x if x.starts_with('-') => return Err(format!("unknown option: {}", x)),
+            x if topic.is_none() => topic = Some(x.to_string()),
+            x => return Err(format!("unexpected argument: {}", x)),
+        }
+    }
+
+    Ok(ListenerArgs {
+        topic: topic.ok_or("no topic is given")?,
+        count,
+        interval,
+    })
+}
+
+fn listen(args: &ListenerArgs, output: &mut dyn Write) -> std::io::Result<()> {
+    let mut rx = match get_formatted_rx_of_message_instance(&args.topic, 0) {
+        Ok(Some(rx)) => rx,
+        Ok(None) => {
+            return writeln!(
+                output,
+                "topic {} has no formatter, see msg::set_message_formatter",
+                args.topic
+            )
+        }
+        Err(e) => return writeln!(output, "{}", e),
+    };
+    let mut timeout = LISTEN_TIMEOUT;
+    if let Some(interval) = args.interval {
+        rx.set_interval(interval);
+        timeout = timeout.saturating_add(interval);
+    }
+
+    let mut printed = 0;
+    while args.count == 0 || printed < args.count {
+        match rx.read_timeout(timeout) {
+            Ok(msg) => writeln!(output, "{}: {}", args.topic, msg)?,
+            Err(ReadError::Timeout) => {
+                return writeln!(
+                    output,
+                    "topic {} is not updated in {:?}",
+                    args.topic, timeout
+                )
+            }
+            Err(ReadError::Closed) => return writeln!(output, "topic {} is closed", args.topic),
+        }
+        printed += 1;
+    }
+    Ok(())
+}
+
+fn listener_main(argc: u32, argv: *const &str) {
+    let args = unsafe { std::slice::from_raw_parts(argv, argc as usize) };
+    // keep one output for the whole run, dropping it closes the client's stdout
+    let mut output = get_output();
+    let result = match parse_args(args.get(1..).unwrap_or_default()) {
+        Ok(args) => listen(&args, &mut output),
+        Err(e) => writeln!(output, "{}\n{}", e, USAGE),
+    };
+    // the client may disconnect while listening, nothing to do then
+    let _ = result.and_then(|_| output.flush());
+}
+
+#[ctor::ctor]
+fn register_listener() {
+    Module::register("listener", listener_main);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msg::{
+        add_message, get_new_tx_of_message, set_message_debug_formatter, set_message_formatter,
+    };
+
+    #[derive(Debug, Clone)]
+    struct Attitude {
+        roll: f32,
+        pitch: f32,
+    }
+
+    #[test]
+    fn test_listener_args() {
+        assert_eq!(
+            parse_args(&["attitude", "-n", "5", "-r", "10"]),
+            Ok(ListenerArgs {
+                topic: "attitude".to_string(),
+                count: 5,
+                interval: Some(Duration::from_millis(100))
+            })
+        );
+        assert_eq!(parse_args(&["attitude"]).unwrap().count, 1);
+        assert!(parse_args(&[]).is_err());
+        assert!(parse_args(&["attitude", "-n"]).is_err());
+        assert!(parse_args(&["attitude", "-r", "0"]).is_err());
+        assert!(parse_args(&["attitude", "-r", "1e-300"]).is_err());
+        assert!(parse_args(&["attitude", "-x"]).is_err());
+    }
+
+    #[test]
+    fn test_listener() {
+        add_message::<Attitude>("test_listener_attitude").unwrap();
+        let tx = get_new_tx_of_message::<Attitude>("test_listener_attitude").unwrap();
+        let args = parse_args(&["test_listener_attitude", "-n", "2"]).unwrap();
+
+        let mut output = Vec::new();
+        listen(&args, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "topic test_listener_attitude has no formatter, see msg::set_message_formatter\n"
+        );
+
+        set_message_debug_formatter::<Attitude>("test_listener_attitude").unwrap();
+        tx.send(Attitude {
+            roll: 1.0,
+            pitch: 2.0,
+        });
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            tx.send(Attitude {
+                roll: 3.0,
+                pitch: 4.0,
+            });
+        });
+        let mut output = Vec::new();
+        listen(&args, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "test_listener_attitude: Attitude { roll: 1.0, pitch: 2.0 }\n\
+             test_listener_attitude: Attitude { roll: 3.0, pitch: 4.0 }\n"
+        );
+
+        // a custom formatter replaces the Debug one
+        set_message_formatter("test_listener_attitude", |x: &Attitude| {
+            format!("roll {:.1} pitch {:.1}", x.roll, x.pitch)
+        })
+        .unwrap();
+        let args = parse_args(&["test_listener_attitude"]).unwrap();
+        let mut output = Vec::new();
+        listen(&args, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "test_listener_attitude: roll 3.0 pitch 4.0\n"
+        );
+
+        // the interval of a slow rate is longer than LISTEN_TIMEOUT
+        let tx = get_new_tx_of_message::<Attitude>("test_listener_attitude").unwrap();
+        tx.send(Attitude {
+            roll: 5.0,
+            pitch: 6.0,
+        });
+        let publisher = std::thread::spawn(move || {
+            for _ in 0..30 {
+                tx.send(Attitude {
+                    roll: 5.0,
+                    pitch: 6.0,
+                });
+                std::thread::sleep(Duration::from_millis(100));
+            }
+        });
+        let args = parse_args(&["test_listener_attitude", "-n", "2", "-r", "0.4"]).unwrap();
+        let mut output = Vec::new();
+        listen(&args, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "test_listener_attitude: roll 5.0 pitch 6.0
+"
+            .repeat(2)
+        );
+        publisher.join().unwrap();
+
+        let args = parse_args(&["test_listener_none"]).unwrap();
+        let mut output = Vec::new();
+        listen(&args, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "message test_listener_none instance 0 is not found\n"
+        );
+    }
+}
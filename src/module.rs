@@ -57,7 +57,9 @@ mod tests {
 
     #[test]
     fn test_module_register() {
-        assert_eq!(MODULE_LIST.read().unwrap().len(), 1);
+        // the built-in listener module is registered too
+        assert_eq!(MODULE_LIST.read().unwrap().len(), 2);
+        assert!(MODULE_LIST.read().unwrap().contains_key("listener"));
         Module::get_module("test").execute(1, std::ptr::null());
     }
 }
@@ -1,64 +1,718 @@
-use std::{sync::{LazyLock, RwLock}, collections::HashMap, any::Any};
-use crate::channel::{Receiver,Sender,Channel};
+use std::{sync::{Arc, LazyLock, Mutex, OnceLock, RwLock, Weak}, collections::HashMap, any::Any, io, time::Duration, marker::PhantomData, fmt};
+use crate::{channel::{Receiver,Sender,Channel,ChannelStats,ReadError}, shm::{Pod,fingerprint}};
 
 pub struct Message<T>{
     pub rx:Receiver<T>,
-    pub tx:Sender<T>
+    pub tx:Sender<T>,
+    priority:u8,
+    pending:bool, // created by a subscriber, waiting for add_message
+    formatter:Option<Formatter<T>>, // used by the listener module to print messages
+    fingerprint:Option<u64> // schema fingerprint, set once the topic crosses processes
 }
 
+type Formatter<T> = Arc<dyn Fn(&T)->String + Send + Sync>;
+
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum MessageError{
+    NotFound{name:String,instance:u32},
+    TypeMismatch{name:String,expected:&'static str,actual:&'static str},
+    SchemaMismatch{name:String,expected:u64,actual:u64}
+}
+
+impl fmt::Display for MessageError{
+    fn fmt(&self,f:&mut fmt::Formatter<'_>)->fmt::Result{
+        match self{
+            MessageError::NotFound{name,instance} => write!(f,"message {} instance {} is not found",name,instance),
+            MessageError::TypeMismatch{name,expected,actual} => write!(f,"message {} is {}, not {}",name,actual,expected),
+            MessageError::SchemaMismatch{name,expected,actual} =>
+                write!(f,"message {} has schema fingerprint {:016x}, not {:016x}",name,actual,expected)
+        }
+    }
+}
+
+impl std::error::Error for MessageError{}
+
+/// a registered topic, returned by `list_topics`.
+#[derive(Debug,Clone,PartialEq)]
+pub struct TopicInfo{
+    pub name:String,
+    pub instance:u32,
+    pub type_name:&'static str,
+    pub size:usize, // size_of the message type
+    pub priority:u8,
+    pub advertised:bool, // false if only subscribed so far
+    pub sender_cnt:u32, // not counting the ones held by the message list
+    pub receiver_cnt:u32,
+    pub fingerprint:Option<u64> // see attach_message_schema
+}
+
+// type erased Message<T>, so a topic could be queried without knowing its type
+trait MessageEntry: Send + Sync{
+    fn as_any(&self)->&dyn Any;
+    fn as_any_mut(&mut self)->&mut dyn Any;
+    fn stats(&self)->ChannelStats;
+    fn type_name(&self)->&'static str;
+    fn size(&self)->usize;
+    fn priority(&self)->u8;
+    fn pending(&self)->bool;
+    fn formatted_rx(&self)->Option<Box<dyn FormattedReceiver>>;
+    fn close(&self);
+    fn fingerprint(&self)->Option<u64>;
+    fn set_fingerprint(&mut self,fingerprint:u64);
+}
+
+// a receiver which prints its messages with the formatter of the topic
+pub(crate) trait FormattedReceiver{
+    fn set_interval(&mut self,interval:Duration);
+    fn read_timeout(&mut self,timeout:Duration)->Result<String,ReadError>;
+}
+
+struct FormattedRx<T>{
+    rx:Receiver<T>,
+    formatter:Formatter<T>
+}
+
+impl<T:Clone> FormattedReceiver for FormattedRx<T>{
+    fn set_interval(&mut self,interval:Duration){
+        self.rx.set_interval(interval);
+    }
+
+    fn read_timeout(&mut self,timeout:Duration)->Result<String,ReadError>{
+        self.rx.read_timeout(timeout).map(|x| (self.formatter)(&x))
+    }
+}
+
+impl<T:Clone + Send + Sync + 'static> MessageEntry for Message<T>{
+    fn as_any(&self)->&dyn Any{
+        self
+    }
+
+    fn as_any_mut(&mut self)->&mut dyn Any{
+        self
+    }
+
+    fn type_name(&self)->&'static str{
+        std::any::type_name::<T>()
+    }
+
+    fn size(&self)->usize{
+        std::mem::size_of::<T>()
+    }
+
+    fn priority(&self)->u8{
+        self.priority
+    }
+
+    fn pending(&self)->bool{
+        self.pending
+    }
+
+    fn formatted_rx(&self)->Option<Box<dyn FormattedReceiver>>{
+        let formatter = self.formatter.clone()?;
+        Some(Box::new(FormattedRx{ rx:self.rx.clone(), formatter }))
+    }
+
+    fn close(&self){
+        self.tx.close();
+    }
+
+    fn fingerprint(&self)->Option<u64>{
+        self.fingerprint
+    }
+
+    fn set_fingerprint(&mut self,fingerprint:u64){
+        self.fingerprint = Some(fingerprint);
+    }
+
+    fn stats(&self)->ChannelStats{
+        let mut stats = self.rx.stats();
+        // don't count the sender and receiver held by the list itself
+        stats.sender_cnt -= 1;
+        stats.receiver_cnt -= 1;
+        stats
+    }
+}
+
+// topic name -> instances of the topic, a topic added without instance is instance 0
 pub struct MessageList{
-    data:HashMap<&'static str,Box<dyn Any>>
+    data:HashMap<String,HashMap<u32,Box<dyn MessageEntry>>>,
+    watchers:Vec<Box<dyn Watcher>> // wildcard subscriptions
 }
 
 
 impl MessageList{
-    pub fn get_message<T:'static>(&self,name:&str)->Option<&Message<T>>{
-        let a = self.data.get(name);
+    pub fn get_message<T:'static>(&self,name:&str)->Result<&Message<T>,MessageError>{
+        self.get_message_instance(name, 0)
+    }
+
+    pub fn get_message_instance<T:'static>(&self,name:&str,instance:u32)->Result<&Message<T>,MessageError>{
+        let a = self.data.get(name).and_then(|x| x.get(&instance));
         if let Some(a) = a{
-            let a = a.downcast_ref::<Message<T>>();
-            a
+            downcast_message(name, a.as_ref())
         }else{
-            None
+            Err(MessageError::NotFound{ name:name.to_string(), instance })
+        }
+    }
+
+    // all instances of a topic, ordered by priority(high first) then instance index
+    fn get_instances<T:'static>(&self,name:&str)->Result<Vec<(u32,&Message<T>)>,MessageError>{
+        let mut instances = self.data.get(name).into_iter().flatten()
+            .map(|(i,msg)| downcast_message(name, msg.as_ref()).map(|msg| (*i,msg)))
+            .collect::<Result<Vec<(u32,&Message<T>)>,MessageError>>()?;
+        if instances.is_empty(){
+            return Err(MessageError::NotFound{ name:name.to_string(), instance:0 });
+        }
+        instances.sort_by_key(|(i,msg)| (std::cmp::Reverse(msg.priority),*i));
+        Ok(instances)
+    }
+
+    // offer the newly advertised `instance` of topic `name` to the wildcard subscriptions
+    fn match_watchers(&mut self,name:&str,instance:u32){
+        self.watchers.retain(|x| x.is_alive());
+        if let Some(msg) = self.data.get(name).and_then(|x| x.get(&instance)){
+            for watcher in &self.watchers{
+                watcher.offer(name, instance, msg.as_ref());
+            }
         }
     }
 }
 
+// a wildcard subscription held by the message list
+trait Watcher: Send + Sync{
+    fn offer(&self,name:&str,instance:u32,msg:&dyn MessageEntry);
+    fn is_alive(&self)->bool;
+}
 
-pub fn add_message<T:Clone + 'static>(name:&'static str){
-    let (tx,rx) = Channel::<T>::new();
+// a topic offered to a watcher, waiting for its receiver to pick it up
+struct MatchedTopic<T>{
+    name:String,
+    instance:u32,
+    priority:u8,
+    rx:Receiver<T>
+}
+
+type Matched<T> = Mutex<Vec<MatchedTopic<T>>>;
+
+struct WildcardWatcher<T>{
+    pattern:String,
+    exact:bool, // match the name as is, used by FailoverReceiver
+    matched:Weak<Matched<T>> // dropped with the WildcardReceiver
+}
+
+impl<T:Clone + Send + Sync + 'static> Watcher for WildcardWatcher<T>{
+    fn offer(&self,name:&str,instance:u32,msg:&dyn MessageEntry){
+        let matches = if self.exact { self.pattern == name }else{ match_pattern(&self.pattern, name) };
+        // topics of other types are skipped, like a pending topic which is not advertised yet
+        if msg.pending() || !matches{
+            return;
+        }
+        if let (Some(matched),Some(msg)) = (self.matched.upgrade(),msg.as_any().downcast_ref::<Message<T>>()){
+            matched.lock().unwrap().push(MatchedTopic{ name:name.to_string(), instance, priority:msg.priority, rx:msg.rx.clone() });
+        }
+    }
+
+    fn is_alive(&self)->bool{
+        self.matched.strong_count() > 0
+    }
+}
+
+// `*` matches exactly one level of the path
+fn match_pattern(pattern:&str,name:&str)->bool{
+    let mut pattern = pattern.split('/');
+    let mut name = name.split('/');
+    loop{
+        match (pattern.next(),name.next()){
+            (None,None) => return true,
+            (Some(p),Some(n)) if p == "*" || p == n => {},
+            _ => return false
+        }
+    }
+}
+
+fn downcast_message<'a,T:'static>(name:&str,msg:&'a dyn MessageEntry)->Result<&'a Message<T>,MessageError>{
+    msg.as_any().downcast_ref::<Message<T>>().ok_or_else(|| MessageError::TypeMismatch{
+        name:name.to_string(),
+        expected:std::any::type_name::<T>(),
+        actual:msg.type_name()
+    })
+}
+
+
+pub fn add_message<T:Clone + Send + Sync + 'static>(name:&str)->Result<(),MessageError>{
+    add_message_instance::<T>(name, 0, 0)
+}
+
+/// add the `instance` of topic `name`, used when several publishers(like redundant sensors)
+/// provide the same topic. a `FailoverReceiver` follows the healthy instance with the highest `priority`.
+/// adding it again with the same type keeps the channel, so its subscribers and publishers stay connected.
+/// returns `TypeMismatch` if the topic is subscribed or added before with another type.
+pub fn add_message_instance<T:Clone + Send + Sync + 'static>(name:&str,instance:u32,priority:u8)->Result<(),MessageError>{
+    let mut list = MESSAGE_LIST.write().unwrap();
+    let instances = list.data.entry(name.to_string()).or_default();
+    let existing = match instances.get_mut(&instance){
+        Some(msg) => {
+            let actual = msg.type_name();
+            Some(msg.as_any_mut().downcast_mut::<Message<T>>().ok_or_else(|| MessageError::TypeMismatch{
+                name:name.to_string(),
+                expected:std::any::type_name::<T>(),
+                actual
+            })?)
+        }
+        None => None
+    };
+    match existing{
+        // bind the topic subscribed before
+        Some(msg) if msg.pending => {
+            msg.pending = false;
+            msg.priority = priority;
+        }
+        Some(_) => return Ok(()),
+        None => {
+            let (tx,rx) = Channel::<T>::new();
+            let msg= Message{
+                rx,
+                tx,
+                priority,
+                pending:false,
+                formatter:None,
+                fingerprint:None
+            };
+            instances.insert(instance, Box::new(msg));
+        }
+    }
+    list.match_watchers(name, instance);
+    Ok(())
+}
+
+/// like add_message, but the topic lives in shared memory, so other processes could
+/// open it with `Channel::open_shm` or `add_shm_message` using the same name.
+/// a topic subscribed before can't be moved to the shared memory, add it before subscribing.
+/// returns an error if the topic is added or subscribed before, wrapping `TypeMismatch` for another type.
+pub fn add_shm_message<T:Pod>(name:&str)->io::Result<()>{
+    let mut list = MESSAGE_LIST.write().unwrap();
+    if let Some(msg) = list.data.get(name).and_then(|x| x.get(&0)){
+        if msg.as_any().downcast_ref::<Message<T>>().is_none(){
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,MessageError::TypeMismatch{
+                name:name.to_string(),
+                expected:std::any::type_name::<T>(),
+                actual:msg.type_name()
+            }));
+        }
+        let e = if msg.pending(){
+            format!("topic {} is subscribed before added to shared memory",name)
+        }else{
+            format!("topic {} is added already",name)
+        };
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists,e));
+    }
+    let (tx,rx) = Channel::<T>::open_shm(name)?;
     let msg= Message{
         rx,
-        tx
+        tx,
+        priority:0,
+        pending:false,
+        formatter:None,
+        fingerprint:Some(fingerprint::<T>())
     };
-    MESSAGE_LIST.write().unwrap().data.insert(name, Box::new(msg));
+    list.data.entry(name.to_string()).or_default().insert(0, Box::new(msg));
+    list.match_watchers(name, 0);
+    Ok(())
 }
 
-pub fn get_new_tx_of_message<T:'static>(name:&str)->Option<Sender<T>>{
+pub fn get_new_tx_of_message<T:Send + Sync + 'static>(name:&str)->Result<Sender<T>,MessageError>{
+    get_new_tx_of_message_instance(name, 0)
+}
+
+pub fn get_new_tx_of_message_instance<T:Send + Sync + 'static>(name:&str,instance:u32)->Result<Sender<T>,MessageError>{
     let list= &MESSAGE_LIST.read().unwrap();
-    let msg = list.get_message(name);
-    if let Some(msg) = msg{
-        Some(msg.tx.clone())
-    }else{
-        None
+    let msg = list.get_message_instance(name,instance)?;
+    Ok(msg.tx.clone())
+}
+
+/// set how messages of every instance of topic `name` are printed by the `listener` module.
+pub fn set_message_formatter<T:'static,F>(name:&str,formatter:F)->Result<(),MessageError>
+    where F:Fn(&T)->String + Send + Sync + 'static{
+    let formatter:Formatter<T> = Arc::new(formatter);
+    let mut list = MESSAGE_LIST.write().unwrap();
+    let instances = list.data.get_mut(name).filter(|x| !x.is_empty())
+        .ok_or_else(|| MessageError::NotFound{ name:name.to_string(), instance:0 })?;
+    for msg in instances.values_mut(){
+        let actual = msg.type_name();
+        let msg = msg.as_any_mut().downcast_mut::<Message<T>>().ok_or_else(|| MessageError::TypeMismatch{
+            name:name.to_string(),
+            expected:std::any::type_name::<T>(),
+            actual
+        })?;
+        msg.formatter = Some(formatter.clone());
     }
+    Ok(())
 }
 
-pub fn get_new_rx_of_message<T:'static>(name:&str)->Option<Receiver<T>>{
-    let list= &MESSAGE_LIST.read().unwrap();
-    let msg = list.get_message(name);
-    if let Some(msg) = msg{
-        Some(msg.rx.clone())
-    }else{
-        None
-    }    
+/// print messages of topic `name` with their `Debug` format.
+pub fn set_message_debug_formatter<T:fmt::Debug + 'static>(name:&str)->Result<(),MessageError>{
+    set_message_formatter(name, |x:&T| format!("{:?}",x))
+}
+
+// subscribe the `instance` of topic `name` without knowing its type, None if it has no formatter
+pub(crate) fn get_formatted_rx_of_message_instance(name:&str,instance:u32)->Result<Option<Box<dyn FormattedReceiver>>,MessageError>{
+    let list = MESSAGE_LIST.read().unwrap();
+    let msg = list.data.get(name).and_then(|x| x.get(&instance))
+        .ok_or_else(|| MessageError::NotFound{ name:name.to_string(), instance })?;
+    Ok(msg.formatted_rx())
+}
+
+/// remove every instance of topic `name` from the message list and close it,
+/// readers get `ReadError::Closed` once they read everything, and messages sent by the
+/// remaining senders are dropped. the channel is freed when its last handle drops,
+/// and `name` could be added again, even with another type.
+pub fn remove_message(name:&str)->Result<(),MessageError>{
+    let instances = MESSAGE_LIST.write().unwrap().data.remove(name)
+        .ok_or_else(|| MessageError::NotFound{ name:name.to_string(), instance:0 })?;
+    for msg in instances.values(){
+        msg.close();
+    }
+    Ok(())
+}
+
+/// record the schema fingerprint(see `shm::fingerprint`) of topic `name` when it's attached
+/// to another process, like by a bridge. returns `SchemaMismatch` if it's attached with another one,
+/// so bytes of a changed layout are never reinterpreted.
+pub fn attach_message_schema(name:&str,fingerprint:u64)->Result<(),MessageError>{
+    let mut list = MESSAGE_LIST.write().unwrap();
+    let instances = list.data.get_mut(name).filter(|x| !x.is_empty())
+        .ok_or_else(|| MessageError::NotFound{ name:name.to_string(), instance:0 })?;
+    if let Some(actual) = instances.values().find_map(|x| x.fingerprint().filter(|x| *x != fingerprint)){
+        return Err(MessageError::SchemaMismatch{ name:name.to_string(), expected:fingerprint, actual });
+    }
+    for msg in instances.values_mut(){
+        msg.set_fingerprint(fingerprint);
+    }
+    Ok(())
+}
+
+/// statistics of the topic `name`(instance 0), see `ChannelStats`.
+pub fn get_message_stats(name:&str)->Option<ChannelStats>{
+    get_message_instance_stats(name, 0)
+}
+
+/// statistics of the `instance` of topic `name`, see `ChannelStats`.
+pub fn get_message_instance_stats(name:&str,instance:u32)->Option<ChannelStats>{
+    MESSAGE_LIST.read().unwrap().data.get(name).and_then(|x| x.get(&instance)).map(|msg| msg.stats())
+}
+
+/// every registered topic, ordered by name and instance.
+pub fn list_topics()->Vec<TopicInfo>{
+    let list = MESSAGE_LIST.read().unwrap();
+    let mut topics:Vec<TopicInfo> = list.data.iter().flat_map(|(name,instances)|{
+        instances.iter().map(|(instance,msg)|{
+            let stats = msg.stats();
+            TopicInfo{
+                name:name.clone(),
+                instance:*instance,
+                type_name:msg.type_name(),
+                size:msg.size(),
+                priority:msg.priority(),
+                advertised:!msg.pending(),
+                sender_cnt:stats.sender_cnt,
+                receiver_cnt:stats.receiver_cnt,
+                fingerprint:msg.fingerprint()
+            }
+        })
+    }).collect();
+    topics.sort_by(|a,b| (&a.name,a.instance).cmp(&(&b.name,b.instance)));
+    topics
+}
+
+/// subscribe the topic `name`. if it's not added yet, a pending topic is created and
+/// `add_message` binds to it later, so the startup order of modules doesn't matter.
+pub fn get_new_rx_of_message<T:Clone + Send + Sync + 'static>(name:&str)->Result<Receiver<T>,MessageError>{
+    get_new_rx_of_message_instance(name, 0)
+}
+
+pub fn get_new_rx_of_message_instance<T:Clone + Send + Sync + 'static>(name:&str,instance:u32)->Result<Receiver<T>,MessageError>{
+    match MESSAGE_LIST.read().unwrap().get_message_instance::<T>(name,instance){
+        Err(MessageError::NotFound{..}) => {},
+        x => return x.map(|msg| msg.rx.clone())
+    }
+
+    let mut list = MESSAGE_LIST.write().unwrap();
+    // check again, it may be added before getting the write lock
+    if !list.data.get(name).is_some_and(|x| x.contains_key(&instance)){
+        let (tx,rx) = Channel::<T>::new();
+        let msg = Message{
+            rx,
+            tx,
+            priority:0,
+            pending:true,
+            formatter:None,
+            fingerprint:None
+        };
+        list.data.entry(name.to_string()).or_default().insert(instance, Box::new(msg));
+    }
+    list.get_message_instance(name,instance).map(|msg| msg.rx.clone())
+}
+
+/// subscribe every topic whose name matches `pattern` and has the message type `T`,
+/// `*` matches one level of the path, like `*/sensors/gyro/*`.
+/// topics advertised later are added to the returned receiver as well.
+pub fn subscribe_wildcard<T:Clone + Send + Sync + 'static>(pattern:&str)->WildcardReceiver<T>{
+    let matched = Arc::new(Mutex::new(Vec::new()));
+    let watcher = WildcardWatcher{ pattern:pattern.to_string(), exact:false, matched:Arc::downgrade(&matched) };
+    let mut list = MESSAGE_LIST.write().unwrap();
+    for (name,instances) in &list.data{
+        for (instance,msg) in instances{
+            watcher.offer(name, *instance, msg.as_ref());
+        }
+    }
+    matched.lock().unwrap().sort_by(|a,b| (&a.name,a.instance).cmp(&(&b.name,b.instance)));
+    list.watchers.push(Box::new(watcher));
+    WildcardReceiver{ pattern:pattern.to_string(), receivers:Vec::new(), matched }
+}
+
+/// receivers of the topics matching a wildcard pattern, see `subscribe_wildcard`.
+/// a matched topic removed by `remove_message` keeps its closed receiver,
+/// until a topic with the same name and instance is added again and replaces it.
+pub struct WildcardReceiver<T>{
+    pattern:String,
+    receivers:Vec<(String,u32,Receiver<T>)>,
+    matched:Arc<Matched<T>> // filled by the message list
+}
+
+impl<T> WildcardReceiver<T>{
+    pub fn pattern(&self)->&str{
+        &self.pattern
+    }
+
+    /// name, instance and receiver of every matched topic, in the order they first matched.
+    pub fn receivers(&mut self)->&mut [(String,u32,Receiver<T>)]{
+        let matched = std::mem::take(&mut *self.matched.lock().unwrap());
+        for x in matched{
+            match self.receivers.iter_mut().find(|(name,instance,_)| *name == x.name && *instance == x.instance){
+                Some(old) => old.2 = x.rx, // added again after removed
+                None => self.receivers.push((x.name,x.instance,x.rx))
+            }
+        }
+        &mut self.receivers
+    }
+}
+
+impl<T:Clone> WildcardReceiver<T>{
+    /// a new message of any matched topic, with the topic name and instance.
+    pub fn try_read(&mut self)->Option<(&str,u32,T)>{
+        self.receivers();
+        self.receivers.iter_mut()
+            .find_map(|(name,instance,rx)| rx.try_read().map(|x| (name.as_str(),*instance,x)))
+    }
+}
+
+/// a path prefix of topic names like `vehicle1/sensors`, so the same module could run
+/// for several vehicles or plugins in one process without name collisions.
+#[derive(Debug,Clone,Default,PartialEq,Eq)]
+pub struct Namespace{
+    prefix:String // without leading and trailing `/`, empty for the root
 }
 
+impl Namespace{
+    pub fn new(path:&str)->Self{
+        Namespace{ prefix:path.trim_matches('/').to_string() }
+    }
+
+    /// the namespace `path` inside this one.
+    pub fn join(&self,path:&str)->Self{
+        Namespace::new(&self.path(path))
+    }
+
+    /// full name of topic `name` in this namespace, a name starting with `/` is absolute.
+    pub fn path(&self,name:&str)->String{
+        match name.strip_prefix('/'){
+            Some(name) => name.to_string(),
+            None if self.prefix.is_empty() => name.to_string(),
+            None => format!("{}/{}",self.prefix,name)
+        }
+    }
+
+    pub fn add_message<T:Clone + Send + Sync + 'static>(&self,name:&str)->Result<(),MessageError>{
+        add_message::<T>(&self.path(name))
+    }
+
+    pub fn add_message_instance<T:Clone + Send + Sync + 'static>(&self,name:&str,instance:u32,priority:u8)->Result<(),MessageError>{
+        add_message_instance::<T>(&self.path(name), instance, priority)
+    }
+
+    pub fn get_new_tx_of_message<T:Send + Sync + 'static>(&self,name:&str)->Result<Sender<T>,MessageError>{
+        get_new_tx_of_message(&self.path(name))
+    }
+
+    pub fn get_new_rx_of_message<T:Clone + Send + Sync + 'static>(&self,name:&str)->Result<Receiver<T>,MessageError>{
+        get_new_rx_of_message(&self.path(name))
+    }
+
+    pub fn remove_message(&self,name:&str)->Result<(),MessageError>{
+        remove_message(&self.path(name))
+    }
+
+    /// like `subscribe_wildcard`, the pattern is relative to this namespace.
+    pub fn subscribe_wildcard<T:Clone + Send + Sync + 'static>(&self,pattern:&str)->WildcardReceiver<T>{
+        subscribe_wildcard(&self.path(pattern))
+    }
+}
+
+/// subscribe all instances of topic `name`, instances added later are followed too.
+/// an instance is healthy if its latest message is not older than `timeout`.
+pub fn get_new_failover_rx_of_message<T:Clone + Send + Sync + 'static>(name:&str,timeout:Duration)->Result<FailoverReceiver<T>,MessageError>{
+    let mut list = MESSAGE_LIST.write().unwrap();
+    let instances:Vec<(u32,u8,Receiver<T>)> = list.get_instances::<T>(name)?.into_iter()
+        .map(|(i,msg)| (i,msg.priority,msg.rx.clone()))
+        .collect();
+    // watch under the same lock, so an instance added right after is never missed
+    let matched = Arc::new(Mutex::new(Vec::new()));
+    list.watchers.push(Box::new(WildcardWatcher{ pattern:name.to_string(), exact:true, matched:Arc::downgrade(&matched) }));
+    Ok(FailoverReceiver{ instances, active:0, timeout, matched })
+}
+
+/*
+    reads from the healthy instance with the highest priority.
+    when the active instance goes stale it switches to the next healthy one,
+    and switches back once a higher priority instance is healthy again.
+    if no instance is healthy, it keeps the active one.
+*/
+pub struct FailoverReceiver<T>{
+    instances:Vec<(u32,u8,Receiver<T>)>, // instance, priority and receiver, ordered by priority
+    active:usize,
+    timeout:Duration,
+    matched:Arc<Matched<T>> // instances advertised after subscribing
+}
+
+impl<T:Clone> FailoverReceiver<T>{
+    // take the instances advertised since the last call, a re-added instance replaces the old one
+    fn update(&mut self){
+        let matched = std::mem::take(&mut *self.matched.lock().unwrap());
+        if matched.is_empty(){
+            return;
+        }
+        let active = self.instances[self.active].0;
+        for x in matched{
+            match self.instances.iter_mut().find(|(i,_,_)| *i == x.instance){
+                Some(old) => *old = (x.instance,x.priority,x.rx),
+                None => self.instances.push((x.instance,x.priority,x.rx))
+            }
+        }
+        self.instances.sort_by_key(|(i,priority,_)| (std::cmp::Reverse(*priority),*i));
+        self.active = self.instances.iter().position(|(i,_,_)| *i == active).unwrap_or(0);
+    }
+
+    fn select(&mut self){
+        self.update();
+        if let Some(idx) = self.instances.iter().position(|(_,_,rx)| rx.age().is_some_and(|x| x <= self.timeout)){
+            self.active = idx;
+        }
+    }
+
+    /// the instance index currently followed.
+    pub fn active_instance(&mut self)->u32{
+        self.select();
+        self.instances[self.active].0
+    }
+
+    pub fn try_read(&mut self)->Option<T>{
+        self.select();
+        self.instances[self.active].2.try_read()
+    }
+
+    /// block until the followed instance has new data, return `ReadError::Closed`
+    /// once every instance is closed.
+    pub fn read(&mut self)->Result<T,ReadError>{
+        loop{
+            if let Some(data) = self.try_read(){
+                return Ok(data);
+            }
+            // wait on the active instance if it's still open,
+            // and wake up every timeout to check whether it's stale
+            let idx = if !self.instances[self.active].2.is_closed(){
+                self.active
+            }else{
+                match self.instances.iter().position(|(_,_,rx)| !rx.is_closed()){
+                    Some(idx) => idx,
+                    None => return Err(ReadError::Closed)
+                }
+            };
+            if let Ok(data) = self.instances[idx].2.read_timeout(self.timeout){
+                return Ok(data);
+            }
+        }
+    }
+}
+
+
+/// declare a topic, the topic is added to the message list at ctor time.
+/// the name is the lowercase static name(`sensor_gyro` below) unless given explicitly.
+/// ```ignore
+/// rpos::topic!(pub SENSOR_GYRO: GyroData);
+/// rpos::topic!(RC_INPUT: RcData = "rc_input");
+///
+/// let tx = SENSOR_GYRO.advertise()?;
+/// let mut rx = SENSOR_GYRO.subscribe()?;
+/// ```
+#[macro_export]
+macro_rules! topic {
+    ($(#[$meta:meta])* $vis:vis $name:ident : $t:ty) => {
+        $crate::topic!($(#[$meta])* $vis $name: $t = "");
+    };
+    ($(#[$meta:meta])* $vis:vis $name:ident : $t:ty = $topic:expr) => {
+        $(#[$meta])*
+        $vis static $name: $crate::msg::Topic<$t> = $crate::msg::Topic::new(stringify!($name), $topic);
+
+        const _: () = {
+            #[$crate::ctor::ctor]
+            fn register_topic() {
+                // nothing to report to at ctor time, subscribe and advertise return the error
+                let _ = $crate::msg::add_message::<$t>($name.name());
+            }
+        };
+    };
+}
+
+/// a topic declared by `topic!`, its name and message type are checked by the compiler.
+pub struct Topic<T>{
+    ident:&'static str,
+    topic:&'static str,
+    name:OnceLock<&'static str>,
+    _marker:PhantomData<fn()->T>
+}
+
+impl<T> Topic<T>{
+    #[doc(hidden)]
+    pub const fn new(ident:&'static str,topic:&'static str)->Self{
+        Topic{ ident, topic, name:OnceLock::new(), _marker:PhantomData }
+    }
+
+    pub fn name(&self)->&'static str{
+        self.name.get_or_init(||{
+            if self.topic.is_empty(){
+                Box::leak(self.ident.to_lowercase().into_boxed_str())
+            }else{
+                self.topic
+            }
+        })
+    }
+}
+
+impl<T:Clone + Send + Sync + 'static> Topic<T>{
+    /// returns `TypeMismatch` if the name is declared or added with another type too.
+    pub fn subscribe(&self)->Result<Receiver<T>,MessageError>{
+        get_new_rx_of_message(self.name())
+    }
+
+    /// returns `TypeMismatch` if the name is declared or added with another type too,
+    /// or `NotFound` if it's removed by `remove_message`.
+    pub fn advertise(&self)->Result<Sender<T>,MessageError>{
+        get_new_tx_of_message(self.name())
+    }
+}
 
-unsafe impl Send for MessageList{}
-unsafe impl Sync for MessageList{}
 
 static MESSAGE_LIST:LazyLock<RwLock<MessageList>> = LazyLock::new(||{
-    RwLock::new(MessageList { data:HashMap::new() })
+    RwLock::new(MessageList { data:HashMap::new(), watchers:Vec::new() })
 });
 
 #[cfg(test)]
@@ -72,9 +726,14 @@ mod tests{
 
     #[ctor::ctor]
     fn ttt(){
-        add_message::<GyroData>("test_gyro");
+        add_message::<GyroData>("test_gyro").unwrap();
     }
 
+    crate::topic!(TEST_TOPIC_GYRO: GyroData);
+    crate::topic!(TEST_TOPIC_NAMED: u32 = "test_topic/named");
+    crate::topic!(TEST_TOPIC_DUP_A: u32 = "test_topic/dup");
+    crate::topic!(TEST_TOPIC_DUP_B: u64 = "test_topic/dup");
+
     #[test]
     fn test_basic_message(){
         let mut rx = get_new_rx_of_message::<GyroData>("test_gyro").unwrap();
@@ -84,7 +743,7 @@ mod tests{
             tx.send(GyroData{ data: [1,2,3] });
         });
 
-        let recv_data = rx.read().data;
+        let recv_data = rx.read().unwrap().data;
 
         assert_eq!(recv_data[0],1);
         assert_eq!(recv_data[1],2);
@@ -92,4 +751,259 @@ mod tests{
         println!("data:{:?}",recv_data);
 
     }
+
+    #[test]
+    fn test_topic(){
+        assert_eq!(TEST_TOPIC_GYRO.name(),"test_topic_gyro");
+        let mut rx = TEST_TOPIC_GYRO.subscribe().unwrap();
+        TEST_TOPIC_GYRO.advertise().unwrap().send(GyroData{ data: [4,5,6] });
+        assert_eq!(rx.try_read().unwrap().data,[4,5,6]);
+        // a topic is an usual message
+        assert!(get_new_rx_of_message::<GyroData>("test_topic_gyro").is_ok());
+
+        assert_eq!(TEST_TOPIC_NAMED.name(),"test_topic/named");
+        let mut rx = TEST_TOPIC_NAMED.subscribe().unwrap();
+        TEST_TOPIC_NAMED.advertise().unwrap().send(1);
+        assert_eq!(rx.try_read(),Some(1));
+
+        // the ctor registered first wins, the other declaration gets the error
+        let (a,b) = (TEST_TOPIC_DUP_A.advertise(),TEST_TOPIC_DUP_B.advertise());
+        assert!(a.is_ok() != b.is_ok());
+        assert!(matches!(TEST_TOPIC_DUP_A.subscribe().err().or(TEST_TOPIC_DUP_B.subscribe().err()),
+            Some(MessageError::TypeMismatch{..})));
+    }
+
+    #[test]
+    fn test_list_topics(){
+        add_message::<[u8;16]>("test_list").unwrap();
+        add_message_instance::<u64>("test_list_instance", 1, 5).unwrap();
+        let _rx = get_new_rx_of_message::<[u8;16]>("test_list").unwrap();
+
+        let topics = list_topics();
+        let topic = topics.iter().find(|x| x.name == "test_list").unwrap();
+        assert_eq!(topic.type_name,"[u8; 16]");
+        assert_eq!(topic.size,16);
+        assert_eq!((topic.sender_cnt,topic.receiver_cnt),(0,1));
+
+        let topic = topics.iter().find(|x| x.name == "test_list_instance").unwrap();
+        assert_eq!((topic.instance,topic.priority,topic.type_name,topic.size),(1,5,"u64",8));
+
+        let gyro = topics.iter().find(|x| x.name == "test_gyro").unwrap();
+        assert!(gyro.type_name.ends_with("GyroData"));
+        assert!(topics.windows(2).all(|x| (&x[0].name,x[0].instance) <= (&x[1].name,x[1].instance)));
+    }
+
+    #[test]
+    fn test_message_error(){
+        // a subscriber creates a pending topic, only the publisher sees the missing topic
+        assert_eq!(get_new_tx_of_message::<GyroData>("test_gyro_none").err().unwrap(),
+            MessageError::NotFound{ name:"test_gyro_none".to_string(), instance:0 });
+        assert_eq!(get_new_tx_of_message_instance::<GyroData>("test_gyro", 1).err().unwrap(),
+            MessageError::NotFound{ name:"test_gyro".to_string(), instance:1 });
+
+        let err = get_new_tx_of_message::<u32>("test_gyro").err().unwrap();
+        assert!(matches!(&err,MessageError::TypeMismatch{ expected:"u32", actual, .. } if actual.ends_with("GyroData")));
+        assert!(err.to_string().starts_with("message test_gyro is "));
+        assert!(get_new_failover_rx_of_message::<u32>("test_gyro", Duration::from_millis(50)).is_err());
+    }
+
+    #[test]
+    fn test_subscribe_before_add(){
+        let mut rx = get_new_rx_of_message::<u32>("test_pending").unwrap();
+        assert!(get_new_tx_of_message::<u32>("test_pending").is_ok());
+        assert!(!list_topics().iter().find(|x| x.name == "test_pending").unwrap().advertised);
+
+        add_message::<u32>("test_pending").unwrap();
+        assert!(list_topics().iter().find(|x| x.name == "test_pending").unwrap().advertised);
+        get_new_tx_of_message::<u32>("test_pending").unwrap().send(1);
+        assert_eq!(rx.try_read(),Some(1));
+
+        // the subscriber's type conflicts with the publisher's
+        let _rx = get_new_rx_of_message::<u32>("test_pending_type").unwrap();
+        assert!(matches!(add_message::<u64>("test_pending_type"),
+            Err(MessageError::TypeMismatch{ expected:"u64", actual:"u32", .. })));
+        assert!(get_new_rx_of_message::<u32>("test_pending_type").is_ok());
+        assert!(matches!(get_new_rx_of_message::<u64>("test_pending_type"),Err(MessageError::TypeMismatch{..})));
+
+        // adding it again keeps the channel
+        let tx = get_new_tx_of_message::<u32>("test_pending").unwrap();
+        add_message::<u32>("test_pending").unwrap();
+        let mut rx2 = get_new_rx_of_message::<u32>("test_pending").unwrap();
+        tx.send(2);
+        assert_eq!(rx.try_read(),Some(2));
+        assert_eq!(rx2.try_read(),Some(2));
+
+        // a shm topic can't take over a subscribed one
+        let name = format!("test_pending_shm_{}",std::process::id());
+        let mut rx = get_new_rx_of_message::<u64>(&name).unwrap();
+        let e = add_shm_message::<u32>(&name).unwrap_err();
+        assert_eq!(e.kind(),io::ErrorKind::InvalidInput);
+        assert!(e.into_inner().unwrap().downcast::<MessageError>().is_ok_and(|x| matches!(*x,MessageError::TypeMismatch{..})));
+        assert_eq!(add_shm_message::<u64>(&name).unwrap_err().kind(),io::ErrorKind::AlreadyExists);
+        add_message::<u64>(&name).unwrap();
+        get_new_tx_of_message::<u64>(&name).unwrap().send(3);
+        assert_eq!(rx.try_read(),Some(3));
+    }
+
+    #[test]
+    fn test_namespace(){
+        let vehicle1 = Namespace::new("test_ns/vehicle1/");
+        let sensors = vehicle1.join("sensors");
+        assert_eq!(sensors.path("gyro/0"),"test_ns/vehicle1/sensors/gyro/0");
+        assert_eq!(sensors.path("/test_ns/clock"),"test_ns/clock");
+        assert_eq!(Namespace::default().path("gyro"),"gyro");
+
+        sensors.add_message::<u32>("gyro/0").unwrap();
+        Namespace::new("test_ns/vehicle2/sensors").add_message::<u32>("gyro/0").unwrap();
+        // the same name with another type is skipped by the wildcard
+        Namespace::new("test_ns/vehicle3/sensors").add_message::<f32>("gyro/0").unwrap();
+        let mut rx = sensors.get_new_rx_of_message::<u32>("gyro/0").unwrap();
+        assert!(get_new_rx_of_message::<u32>("test_ns/vehicle1/sensors/gyro/0").is_ok());
+
+        let mut gyros = Namespace::new("test_ns").subscribe_wildcard::<u32>("*/sensors/gyro/*");
+        assert_eq!(gyros.pattern(),"test_ns/*/sensors/gyro/*");
+        let names:Vec<String> = gyros.receivers().iter().map(|x| x.0.clone()).collect();
+        assert_eq!(names,["test_ns/vehicle1/sensors/gyro/0","test_ns/vehicle2/sensors/gyro/0"]);
+
+        // topics advertised later match too, a subscribed but not advertised one doesn't
+        let _pending = get_new_rx_of_message::<u32>("test_ns/vehicle4/sensors/gyro/0").unwrap();
+        add_message::<u32>("test_ns/vehicle2/sensors/gyro/1").unwrap();
+        add_message::<u32>("test_ns/vehicle2/sensors/accel/0").unwrap();
+        assert_eq!(gyros.receivers().len(),3);
+        add_message::<u32>("test_ns/vehicle4/sensors/gyro/0").unwrap();
+        assert_eq!(gyros.receivers()[3].0,"test_ns/vehicle4/sensors/gyro/0");
+
+        get_new_tx_of_message::<u32>("test_ns/vehicle2/sensors/gyro/1").unwrap().send(7);
+        assert_eq!(gyros.try_read(),Some(("test_ns/vehicle2/sensors/gyro/1",0,7)));
+        assert!(gyros.try_read().is_none());
+
+        // a removed and added again topic replaces its old receiver
+        remove_message("test_ns/vehicle2/sensors/gyro/1").unwrap();
+        add_message::<u32>("test_ns/vehicle2/sensors/gyro/1").unwrap();
+        assert_eq!(gyros.receivers().len(),4);
+        get_new_tx_of_message::<u32>("test_ns/vehicle2/sensors/gyro/1").unwrap().send(8);
+        assert_eq!(gyros.try_read(),Some(("test_ns/vehicle2/sensors/gyro/1",0,8)));
+
+        sensors.get_new_tx_of_message::<u32>("gyro/0").unwrap().send(1);
+        assert_eq!(rx.try_read(),Some(1));
+
+        // the list forgets a dropped wildcard subscription
+        drop(gyros);
+        add_message::<u32>("test_ns/vehicle5/sensors/gyro/0").unwrap();
+        assert!(MESSAGE_LIST.read().unwrap().watchers.iter().all(|x| x.is_alive()));
+    }
+
+    #[test]
+    fn test_message_schema(){
+        add_message::<[u32;2]>("test_schema").unwrap();
+        assert_eq!(list_topics().iter().find(|x| x.name == "test_schema").unwrap().fingerprint,None);
+        attach_message_schema("test_schema", fingerprint::<[u32;2]>()).unwrap();
+        attach_message_schema("test_schema", fingerprint::<[u32;2]>()).unwrap();
+        assert_eq!(list_topics().iter().find(|x| x.name == "test_schema").unwrap().fingerprint,
+            Some(fingerprint::<[u32;2]>()));
+
+        // another process attaches the same bytes with another layout
+        let e = attach_message_schema("test_schema", fingerprint::<[f32;2]>()).unwrap_err();
+        assert_eq!(e,MessageError::SchemaMismatch{
+            name:"test_schema".to_string(),
+            expected:fingerprint::<[f32;2]>(),
+            actual:fingerprint::<[u32;2]>()
+        });
+        assert!(e.to_string().contains("schema fingerprint"));
+        assert!(matches!(attach_message_schema("test_schema_none", 1),Err(MessageError::NotFound{..})));
+    }
+
+    #[test]
+    fn test_remove_message(){
+        add_message::<u32>("test_remove").unwrap();
+        let tx = get_new_tx_of_message::<u32>("test_remove").unwrap();
+        let mut rx = get_new_rx_of_message::<u32>("test_remove").unwrap();
+        tx.send(1);
+
+        std::thread::spawn(||{
+            std::thread::sleep(Duration::from_millis(10));
+            remove_message("test_remove").unwrap();
+        });
+        assert_eq!(rx.read().unwrap(),1);
+        // woken by the removal, though the publisher still holds a sender
+        assert_eq!(rx.read().unwrap_err(),ReadError::Closed);
+        assert!(list_topics().iter().all(|x| x.name != "test_remove"));
+        assert!(matches!(remove_message("test_remove"),Err(MessageError::NotFound{..})));
+        drop(rx);
+
+        // re-advertise with another type
+        add_message::<String>("test_remove").unwrap();
+        let mut rx = get_new_rx_of_message::<String>("test_remove").unwrap();
+        get_new_tx_of_message::<String>("test_remove").unwrap().send("hello".to_string());
+        assert_eq!(rx.try_read().unwrap(),"hello");
+
+        // the old channel is freed with its last handles
+        tx.send(2);
+        assert_eq!(tx.stats().receiver_cnt,0);
+
+        // a reader blocked on a shm topic is woken too
+        let name = format!("test_remove_shm_{}",std::process::id());
+        add_shm_message::<u32>(&name).unwrap();
+        let mut rx = get_new_rx_of_message::<u32>(&name).unwrap();
+        let reader = std::thread::spawn(move ||rx.read());
+        std::thread::sleep(Duration::from_millis(10));
+        remove_message(&name).unwrap();
+        assert_eq!(reader.join().unwrap().unwrap_err(),ReadError::Closed);
+        crate::shm::unlink(&name).unwrap();
+    }
+
+    #[test]
+    fn test_message_stats(){
+        add_message::<u32>("test_stats").unwrap();
+        let _rx = get_new_rx_of_message::<u32>("test_stats").unwrap();
+        let tx = get_new_tx_of_message::<u32>("test_stats").unwrap();
+        tx.send(1);
+        tx.send(2);
+
+        let stats = get_message_stats("test_stats").unwrap();
+        assert_eq!(stats.publish_cnt,2);
+        assert_eq!(stats.sender_cnt,1);
+        assert_eq!(stats.receiver_cnt,1);
+        assert!(get_message_stats("test_stats_none").is_none());
+
+        add_message_instance::<u32>("test_stats", 1, 0).unwrap();
+        get_new_tx_of_message_instance::<u32>("test_stats", 1).unwrap().send(3);
+        assert_eq!(get_message_instance_stats("test_stats", 1).unwrap().publish_cnt,1);
+        assert_eq!(get_message_stats("test_stats").unwrap().publish_cnt,2);
+        assert!(get_message_instance_stats("test_stats", 2).is_none());
+    }
+
+    #[test]
+    fn test_message_failover(){
+        add_message_instance::<u32>("test_failover", 0, 10).unwrap();
+        add_message_instance::<u32>("test_failover", 1, 20).unwrap();
+        let tx0 = get_new_tx_of_message_instance::<u32>("test_failover", 0).unwrap();
+        let tx1 = get_new_tx_of_message_instance::<u32>("test_failover", 1).unwrap();
+        let mut rx = get_new_failover_rx_of_message::<u32>("test_failover", Duration::from_millis(50)).unwrap();
+        assert!(get_new_failover_rx_of_message::<u32>("test_failover_none", Duration::from_millis(50)).is_err());
+
+        // instance 1 has the higher priority
+        tx0.send(1);
+        tx1.send(2);
+        assert_eq!(rx.read().unwrap(),2);
+        assert_eq!(rx.active_instance(),1);
+
+        // instance 1 goes stale
+        std::thread::sleep(Duration::from_millis(100));
+        tx0.send(3);
+        assert_eq!(rx.read().unwrap(),3);
+        assert_eq!(rx.active_instance(),0);
+
+        // and recovers
+        tx1.send(4);
+        assert_eq!(rx.read().unwrap(),4);
+        assert_eq!(rx.active_instance(),1);
+
+        // an instance added later with the highest priority takes over
+        add_message_instance::<u32>("test_failover", 2, 30).unwrap();
+        let tx2 = get_new_tx_of_message_instance::<u32>("test_failover", 2).unwrap();
+        tx2.send(5);
+        assert_eq!(rx.read().unwrap(),5);
+        assert_eq!(rx.active_instance(),2);
+    }
 }
\ No newline at end of file
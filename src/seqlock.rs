@@ -0,0 +1,286 @@
+use std::{
+    cell::UnsafeCell,
+    hint::spin_loop,
+    mem::MaybeUninit,
+    ptr::{self, null},
+    sync::atomic::{fence, AtomicBool, AtomicU32, Ordering},
+    time::{Duration, Instant},
+};
+
+// a write in shared memory taking longer than this is taken as abandoned by a killed process
+const STALE_WRITE: Duration = Duration::from_millis(100);
+// spins between checks of the clock while waiting for a write
+const SPINS_PER_CHECK: u32 = 1024;
+
+/*
+    single slot seqlock.
+    seq is odd while a write is in progress, cnt is the number of finished writes.
+    seq and cnt wrap around, so whether it's ever written is a flag of its own.
+    readers retry when they see a write in progress, so they never block the writer.
+    blocked readers futex wait on seq, it works across processes when the seqlock is in
+    shared memory. there a writer may be killed in the middle of a write and leave seq odd,
+    so a write stalled for STALE_WRITE is ended by the next reader or writer.
+*/
+#[repr(C)]
+pub(crate) struct SeqLock<T> {
+    seq: AtomicU32,
+    cnt: AtomicU32, // only changed under the odd seq, wraps like the cnt of locked channels
+    written: AtomicBool,
+    in_process: bool,   // false in shared memory, which is filled with zero
+    waiters: AtomicU32, // readers in futex wait, the writer skips the syscall if none
+    data: UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T: Send> Send for SeqLock<T> {}
+unsafe impl<T: Send> Sync for SeqLock<T> {}
+
+impl<T: Copy> SeqLock<T> {
+    // the data is copied bitwise in read, so only Copy types could create a seqlock
+    pub(crate) fn new() -> Self {
+        Self {
+            seq: AtomicU32::new(0),
+            cnt: AtomicU32::new(0),
+            written: AtomicBool::new(false),
+            in_process: true,
+            waiters: AtomicU32::new(0),
+            data: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+}
+
+impl<T> SeqLock<T> {
+    /// return the write count including this write.
+    pub(crate) fn write(&self, value: T) -> u32 {
+        // writers are serialized by the odd seq, they only spin on each other
+        let seq = loop {
+            let seq = self.unlocked_seq();
+            if self
+                .seq
+                .compare_exchange_weak(
+                    seq,
+                    seq.wrapping_add(1),
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                break seq;
+            }
+        };
+        fence(Ordering::Release);
+
+        unsafe { ptr::write_volatile((*self.data.get()).as_mut_ptr(), value) };
+        let cnt = self.cnt.load(Ordering::Relaxed).wrapping_add(1);
+        self.cnt.store(cnt, Ordering::Relaxed);
+        self.written.store(true, Ordering::Relaxed);
+
+        // the write may be stalled long enough to be ended by another process, keep its seq then
+        let _ = self.seq.compare_exchange(
+            seq.wrapping_add(1),
+            seq.wrapping_add(2),
+            Ordering::Release,
+            Ordering::Relaxed,
+        );
+        cnt
+    }
+
+    /// return the write count and a copy of the data, or None if it's never written.
+    pub(crate) fn read(&self) -> Option<(u32, T)> {
+        loop {
+            let seq = self.unlocked_seq();
+            // never set back, and set before the first write ends
+            if !self.written.load(Ordering::Relaxed) {
+                return None;
+            }
+
+            let cnt = self.cnt.load(Ordering::Relaxed);
+            let value = unsafe { ptr::read_volatile((*self.data.get()).as_ptr()) };
+            fence(Ordering::Acquire);
+
+            if self.seq.load(Ordering::Relaxed) == seq {
+                return Some((cnt, value));
+            }
+        }
+    }
+
+    // spin until no write is in progress and return the even seq.
+    // a stalled write in shared memory is ended here, the message it left may be torn,
+    // which is still a valid Pod.
+    fn unlocked_seq(&self) -> u32 {
+        let mut stalled: Option<(u32, Instant)> = None;
+        let mut spins = 0u32;
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq & 1 == 0 {
+                return seq;
+            }
+            spin_loop();
+            spins = spins.wrapping_add(1);
+            if self.in_process || !spins.is_multiple_of(SPINS_PER_CHECK) {
+                continue;
+            }
+            match stalled {
+                Some((x, since)) if x == seq => {
+                    if since.elapsed() >= STALE_WRITE
+                        && self
+                            .seq
+                            .compare_exchange(
+                                seq,
+                                seq.wrapping_add(1),
+                                Ordering::AcqRel,
+                                Ordering::Relaxed,
+                            )
+                            .is_ok()
+                    {
+                        self.wake();
+                    }
+                }
+                _ => stalled = Some((seq, Instant::now())),
+            }
+            std::thread::yield_now();
+        }
+    }
+
+    pub(crate) fn written(&self) -> bool {
+        self.written.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn cnt(&self) -> u32 {
+        self.cnt.load(Ordering::Acquire)
+    }
+
+    // wake the blocked readers, called after each write
+    pub(crate) fn wake(&self) {
+        fence(Ordering::SeqCst);
+        if self.waiters.load(Ordering::SeqCst) > 0 {
+            futex(&self.seq, libc::FUTEX_WAKE, i32::MAX as u32, null());
+        }
+    }
+
+    // block until the write count is no longer `last_cnt`, or timeout.
+    // may return early, the caller should check the count again.
+    pub(crate) fn wait(&self, last_cnt: u32, timeout: Option<Duration>) {
+        // load seq first, a write after it changes seq and the futex returns at once.
+        // an odd seq is waited on as well, so an abandoned write never makes readers spin
+        let seq = self.seq.load(Ordering::Acquire);
+        if self.cnt.load(Ordering::Acquire) != last_cnt {
+            return;
+        }
+
+        let timeout = timeout.map(|x| libc::timespec {
+            tv_sec: x.as_secs() as libc::time_t,
+            tv_nsec: x.subsec_nanos() as libc::c_long,
+        });
+        let timeout_ptr = match &timeout {
+            Some(x) => x as *const libc::timespec,
+            None => null(),
+        };
+
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        // the kernel checks seq again, so a write after the load above is never missed
+        futex(&self.seq, libc::FUTEX_WAIT, seq, timeout_ptr);
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn futex(addr: &AtomicU32, op: libc::c_int, val: u32, timeout: *const libc::timespec) {
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            addr.as_ptr(),
+            op,
+            val,
+            timeout,
+            null::<u32>(),
+            0,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seqlock_no_torn_read() {
+        let lock = std::sync::Arc::new(SeqLock::<[u64; 8]>::new());
+        assert!(lock.read().is_none());
+
+        let writer = lock.clone();
+        let handle = std::thread::spawn(move || {
+            for i in 1..=100000u64 {
+                writer.write([i; 8]);
+            }
+        });
+
+        while !handle.is_finished() {
+            if let Some((_, data)) = lock.read() {
+                assert!(data.iter().all(|x| *x == data[0]));
+            }
+        }
+        assert_eq!(lock.read().unwrap(), (100000, [100000; 8]));
+    }
+
+    #[test]
+    fn test_seqlock_wrap() {
+        let lock = SeqLock::<u32>::new();
+        // just before seq and cnt wrap around
+        lock.seq.store(u32::MAX - 1, Ordering::Relaxed);
+        lock.cnt.store(u32::MAX - 1, Ordering::Relaxed);
+        assert!(lock.read().is_none());
+
+        assert_eq!(lock.write(1), u32::MAX);
+        assert_eq!(lock.write(2), 0);
+        assert_eq!(lock.read(), Some((0, 2)));
+        assert_eq!(lock.write(3), 1);
+        assert_eq!(lock.read(), Some((1, 3)));
+        assert_eq!(lock.cnt(), 1);
+        assert!(lock.written());
+    }
+
+    #[test]
+    fn test_seqlock_abandoned_write() {
+        let mut lock = SeqLock::<u32>::new();
+        lock.in_process = false;
+        lock.write(1);
+        // a writer killed in the middle of a write
+        lock.seq.fetch_add(1, Ordering::Relaxed);
+
+        let start = Instant::now();
+        assert_eq!(lock.read(), Some((1, 1)));
+        assert!(start.elapsed() >= STALE_WRITE);
+        assert_eq!(lock.write(2), 2);
+        assert_eq!(lock.read(), Some((2, 2)));
+
+        lock.seq.fetch_add(1, Ordering::Relaxed);
+        assert_eq!(lock.write(3), 3);
+        assert_eq!(lock.read(), Some((3, 3)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // a blocked reader sleeps instead of spinning on the odd seq
+        lock.seq.fetch_add(1, Ordering::Relaxed);
+        let start = Instant::now();
+        lock.wait(3, Some(Duration::from_millis(10)));
+        assert!(start.elapsed() >= Duration::from_millis(10));
+    }
+
+    #[test]
+    fn test_seqlock_wait() {
+        let lock = std::sync::Arc::new(SeqLock::<u32>::new());
+        lock.wait(0, Some(Duration::from_millis(10)));
+        assert_eq!(lock.cnt(), 0);
+
+        let writer = lock.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            writer.write(1);
+            writer.wake();
+        });
+        // woken by the write long before the timeout
+        let start = std::time::Instant::now();
+        while lock.cnt() == 0 {
+            lock.wait(0, Some(Duration::from_secs(10)));
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
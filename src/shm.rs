@@ -0,0 +1,402 @@
+use std::{
+    ffi::CString,
+    io,
+    mem::{size_of, MaybeUninit},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    ptr::{null_mut, NonNull},
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+};
+
+use crate::{
+    channel::{Channel, Receiver, Sender, SeqLockSlot, Stamped},
+    seqlock::SeqLock,
+};
+
+/// marker for message types which could be copied between processes byte by byte.
+/// implement `schema` with `pod_schema!`, so reordered or retyped fields change the fingerprint.
+///
+/// # Safety
+/// the type must not hold pointers, references or handles that are only valid in one process,
+/// and its layout must be the same in every process that opens the topic(use `#[repr(C)]`).
+/// it must have no padding bytes, and every bit pattern must be a valid value(so no `bool`,
+/// `char` or enums), as messages are read back from raw bytes sent by other processes.
+pub unsafe trait Pod: Copy + Send + Sync + 'static {
+    /// describes the layout of the type, topics crossing processes compare its `fingerprint`.
+    fn schema() -> String;
+}
+
+macro_rules! impl_pod {
+    ($($t:ty),*) => {
+        $(unsafe impl Pod for $t {
+            fn schema() -> String {
+                format!("{}({})", short_type_name::<Self>(), size_of::<Self>())
+            }
+        })*
+    };
+}
+
+impl_pod!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {
+    fn schema() -> String {
+        format!("[{};{}]", T::schema(), N)
+    }
+}
+
+/// implement `Pod::schema` with the name, type and offset of every field.
+/// every field of the struct must be listed with its declared type, or it does not compile.
+/// ```ignore
+/// #[repr(C)]
+/// #[derive(Clone, Copy)]
+/// struct GyroData { x: f32, y: f32, z: f32 }
+///
+/// unsafe impl rpos::shm::Pod for GyroData {
+///     rpos::pod_schema!(GyroData { x: f32, y: f32, z: f32 });
+/// }
+/// ```
+#[macro_export]
+macro_rules! pod_schema {
+    ($t:ident { $($field:ident : $ft:ty),* $(,)? }) => {
+        fn schema() -> String {
+            // fail to compile if a field is missing or listed with another type
+            let _ = |s: &$t| {
+                let $t { $($field: _),* } = s;
+                $(let _: &$ft = &s.$field;)*
+            };
+            let fields: &[String] = &[$(
+                format!(
+                    "{}:{}@{}",
+                    stringify!($field),
+                    <$ft as $crate::shm::Pod>::schema(),
+                    std::mem::offset_of!($t, $field)
+                )
+            ),*];
+            format!("{}{{{}}}({})", stringify!($t), fields.join(","), std::mem::size_of::<$t>())
+        }
+    };
+}
+
+/// hash of `T::schema()`, the same in every process and build(fnv-1a).
+pub fn fingerprint<T: Pod>() -> u64 {
+    T::schema().bytes().fold(0xcbf29ce484222325, |hash, x| {
+        (hash ^ x as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+// type name without module paths, processes built from different crates agree on it
+fn short_type_name<T: ?Sized>() -> String {
+    let mut name = String::new();
+    let mut ident_start = 0;
+    let mut chars = std::any::type_name::<T>().chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == ':' && chars.peek() == Some(&':') {
+            chars.next();
+            name.truncate(ident_start);
+        } else {
+            name.push(c);
+            if !(c.is_alphanumeric() || c == '_') {
+                ident_start = name.len();
+            }
+        }
+    }
+    name
+}
+
+const SHM_MAGIC: u32 = 0x52504f53; // "RPOS"
+
+/*
+    layout of a topic in shared memory.
+    a new shm object is filled with zero, which is already an empty seqlock,
+    so processes racing to create the same topic only need to agree on the size.
+*/
+#[repr(C)]
+struct ShmHeader<T> {
+    magic: AtomicU32,
+    size: AtomicU32,
+    fingerprint: AtomicU64,
+    seqlock: SeqLock<Stamped<T>>,
+}
+
+pub(crate) struct ShmTopic<T> {
+    header: NonNull<ShmHeader<T>>,
+}
+
+unsafe impl<T: Send> Send for ShmTopic<T> {}
+unsafe impl<T: Send> Sync for ShmTopic<T> {}
+
+// `/` is not allowed in shm names, so it's mapped to `.` and names with `.` are rejected,
+// otherwise `a/b` and `a.b` would share one object
+fn shm_path(name: &str) -> io::Result<CString> {
+    if name.contains('.') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("shm topic name {} contains '.'", name),
+        ));
+    }
+    CString::new(format!("/rpos.{}", name.replace('/', ".")))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+impl<T: Pod> ShmTopic<T> {
+    fn open(name: &str) -> io::Result<Self> {
+        let path = shm_path(name)?;
+        let len = size_of::<ShmHeader<T>>();
+
+        let fd = unsafe {
+            libc::shm_open(
+                path.as_ptr(),
+                libc::O_CREAT | libc::O_RDWR | libc::O_CLOEXEC,
+                // only processes of the same user or group could publish to the topic
+                0o660,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut stat = MaybeUninit::<libc::stat>::zeroed();
+        if unsafe { libc::fstat(fd.as_raw_fd(), stat.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let cur_len = unsafe { stat.assume_init() }.st_size as usize;
+        if cur_len == 0 {
+            if unsafe { libc::ftruncate(fd.as_raw_fd(), len as libc::off_t) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        } else if cur_len != len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("shm topic {} has {} bytes, expected {}", name, cur_len, len),
+            ));
+        }
+
+        let ptr = unsafe {
+            libc::mmap(
+                null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let topic = Self {
+            header: NonNull::new(ptr as *mut ShmHeader<T>).unwrap(),
+        };
+
+        let header = topic.header();
+        let size = size_of::<T>() as u32;
+        match header
+            .size
+            .compare_exchange(0, size, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => {}
+            Err(x) if x == size => {}
+            Err(x) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "shm topic {} holds {} bytes messages, expected {}",
+                        name, x, size
+                    ),
+                ))
+            }
+        }
+        let fingerprint = fingerprint::<T>();
+        match header.fingerprint.compare_exchange(
+            0,
+            fingerprint,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => {}
+            Err(x) if x == fingerprint => {}
+            Err(x) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "shm topic {} has schema fingerprint {:016x}, expected {:016x}({})",
+                        name,
+                        x,
+                        fingerprint,
+                        T::schema()
+                    ),
+                ))
+            }
+        }
+        match header
+            .magic
+            .compare_exchange(0, SHM_MAGIC, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => {}
+            Err(SHM_MAGIC) => {}
+            Err(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{:?} is not a rpos topic", path),
+                ))
+            }
+        }
+        Ok(topic)
+    }
+}
+
+impl<T> ShmTopic<T> {
+    fn header(&self) -> &ShmHeader<T> {
+        unsafe { self.header.as_ref() }
+    }
+
+    pub(crate) fn seqlock(&self) -> &SeqLock<Stamped<T>> {
+        &self.header().seqlock
+    }
+}
+
+impl<T> Drop for ShmTopic<T> {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(
+                self.header.as_ptr() as *mut libc::c_void,
+                size_of::<ShmHeader<T>>(),
+            );
+        }
+    }
+}
+
+impl<T> Channel<T>
+where
+    T: Pod,
+{
+    /// open the topic `name` in shared memory, create it if no process did.
+    /// every process opening the same name shares the data, callbacks and `ReceiverSet`
+    /// are only triggered by senders in the same process.
+    pub fn open_shm(name: &str) -> io::Result<(Sender<T>, Receiver<T>)> {
+        let topic = ShmTopic::open(name)?;
+        Ok(Self::create(0, Some(SeqLockSlot::Shared(topic))))
+    }
+}
+
+/// remove the topic `name` from shared memory, processes which already opened it keep working.
+pub fn unlink(name: &str) -> io::Result<()> {
+    let path = shm_path(name)?;
+    if unsafe { libc::shm_unlink(path.as_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_shm_rxtx() {
+        let name = format!("test_shm_rxtx_{}", std::process::id());
+        // every open maps the topic again, just like another process does
+        let (tx, _) = Channel::<[u32; 4]>::open_shm(&name).unwrap();
+        let (_, mut rx) = Channel::<[u32; 4]>::open_shm(&name).unwrap();
+        assert!(rx.try_read().is_none());
+
+        tx.send([1, 2, 3, 4]);
+        assert_eq!(rx.try_read(), Some([1, 2, 3, 4]));
+
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            tx.send([5; 4]);
+        });
+        assert_eq!(rx.read().unwrap(), [5; 4]);
+
+        // a late subscriber gets the latest data
+        let (_, mut rx2) = Channel::<[u32; 4]>::open_shm(&name).unwrap();
+        assert_eq!(rx2.try_read(), Some([5; 4]));
+
+        unlink(&name).unwrap();
+    }
+
+    #[test]
+    fn test_shm_name() {
+        assert_eq!(
+            shm_path("vehicle1/gyro").unwrap().to_str().unwrap(),
+            "/rpos.vehicle1.gyro"
+        );
+        assert_eq!(
+            Channel::<u32>::open_shm("vehicle1.gyro")
+                .err()
+                .unwrap()
+                .kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            unlink("vehicle1.gyro").unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn test_shm_size_mismatch() {
+        let name = format!("test_shm_size_{}", std::process::id());
+        let _a = Channel::<u32>::open_shm(&name).unwrap();
+        assert_eq!(
+            Channel::<[u64; 2]>::open_shm(&name).err().unwrap().kind(),
+            io::ErrorKind::InvalidData
+        );
+        unlink(&name).unwrap();
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct Attitude {
+        roll: f32,
+        count: u32,
+    }
+
+    unsafe impl Pod for Attitude {
+        crate::pod_schema!(Attitude {
+            roll: f32,
+            count: u32
+        });
+    }
+
+    // the same size with the fields swapped
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct AttitudeSwapped {
+        count: u32,
+        roll: f32,
+    }
+
+    unsafe impl Pod for AttitudeSwapped {
+        crate::pod_schema!(AttitudeSwapped {
+            count: u32,
+            roll: f32
+        });
+    }
+
+    #[test]
+    fn test_shm_schema() {
+        assert_eq!(
+            Attitude::schema(),
+            "Attitude{roll:f32(4)@0,count:u32(4)@4}(8)"
+        );
+        assert_eq!(<[u16; 3]>::schema(), "[u16(2);3]");
+        assert_eq!(
+            short_type_name::<Option<Vec<String>>>(),
+            "Option<Vec<String>>"
+        );
+        assert_eq!(fingerprint::<Attitude>(), fingerprint::<Attitude>());
+        assert_ne!(fingerprint::<Attitude>(), fingerprint::<AttitudeSwapped>());
+        assert_ne!(fingerprint::<u32>(), fingerprint::<f32>());
+
+        let name = format!("test_shm_schema_{}", std::process::id());
+        let _a = Channel::<Attitude>::open_shm(&name).unwrap();
+        let e = Channel::<AttitudeSwapped>::open_shm(&name).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains("schema fingerprint"));
+        assert!(Channel::<Attitude>::open_shm(&name).is_ok());
+        unlink(&name).unwrap();
+    }
+}
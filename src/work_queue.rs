@@ -0,0 +1,78 @@
+use std::{
+    collections::VecDeque,
+    sync::{Condvar, LazyLock, Mutex},
+};
+
+use crate::pthread::create_phtread;
+
+pub static WORK_QUEUE: LazyLock<Box<WorkQueue>> = LazyLock::new(WorkQueue::new);
+
+type Work = Box<dyn FnOnce() + Send + 'static>;
+
+/*
+    a fifo of works run one by one on a dedicated pthread,
+    used to move slow jobs(like deferred msg callbacks) off the caller's thread.
+*/
+pub struct WorkQueue {
+    list: Mutex<VecDeque<Work>>,
+    condvar: Condvar,
+    thread_id: libc::pthread_t,
+}
+
+#[allow(unreachable_code)]
+extern "C" fn work_queue_run(ptr: *mut libc::c_void) -> *mut libc::c_void {
+    let work_queue = unsafe { &*(ptr as *mut WorkQueue) };
+
+    loop {
+        let work = {
+            let list = work_queue.list.lock().unwrap();
+            let mut list = work_queue
+                .condvar
+                .wait_while(list, |x| x.is_empty())
+                .unwrap();
+            list.pop_front().unwrap()
+        };
+        // lock is released here, so the work could add new works
+        work();
+    }
+    std::ptr::null_mut()
+}
+
+impl WorkQueue {
+    fn new() -> Box<Self> {
+        let mut queue = Box::new(WorkQueue {
+            list: Mutex::new(VecDeque::new()),
+            condvar: Condvar::new(),
+            thread_id: 0,
+        });
+
+        let queue_ptr = &mut *queue as *mut WorkQueue as *mut libc::c_void;
+        queue.thread_id = create_phtread(1024 * 1024, 50, work_queue_run, queue_ptr, false);
+        queue
+    }
+
+    pub fn add<F>(&self, work: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.list.lock().unwrap().push_back(Box::new(work));
+        self.condvar.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_work_queue_order() {
+        let (tx, rx) = mpsc::channel();
+        for i in 0..10 {
+            let tx = tx.clone();
+            WORK_QUEUE.add(move || tx.send(i).unwrap());
+        }
+        let result: Vec<i32> = rx.iter().take(10).collect();
+        assert_eq!(result, (0..10).collect::<Vec<i32>>());
+    }
+}